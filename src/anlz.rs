@@ -0,0 +1,261 @@
+use byteorder::*;
+use std::collections::HashMap;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::rekordbox::{
+    normalize_location, parse_lighting_cue, seconds_to_beats, CueKind, XmlCueInfo, XmlTrackInfo,
+};
+
+// Layouts follow the Deep Symmetry crate-digger notes on rekordbox analysis files:
+// a "PMAI" file header followed by big-endian tagged sections.
+
+struct AnlzTag<'a> {
+    fourcc: &'a [u8],
+    len_header: usize,
+    data: &'a [u8],
+}
+
+struct AnlzCue {
+    seconds: f64,
+    loop_end_seconds: Option<f64>,
+    // 1 for hot cue A, 0 for memory cues
+    hot_cue: u32,
+    is_loop: bool,
+    comment: Option<String>,
+    color: Option<(u8, u8, u8)>,
+}
+
+pub struct AnlzAnalysis {
+    pub location: String,
+    pub cues: Vec<XmlCueInfo>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    return Some(BigEndian::read_u16(bytes.get(offset..offset + 2)?));
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    return Some(BigEndian::read_u32(bytes.get(offset..offset + 4)?));
+}
+
+fn read_utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(BigEndian::read_u16).collect();
+    return String::from_utf16_lossy(&units)
+        .trim_matches(char::from(0))
+        .to_string();
+}
+
+fn read_tags(bytes: &[u8]) -> Option<Vec<AnlzTag<'_>>> {
+    if bytes.get(0..4)? != b"PMAI" {
+        return None;
+    }
+    let mut pos = read_u32(bytes, 4)? as usize;
+    let mut tags = Vec::new();
+    while pos + 12 <= bytes.len() {
+        let len_header = read_u32(bytes, pos + 4)? as usize;
+        let len_tag = read_u32(bytes, pos + 8)? as usize;
+        if len_tag < 12 {
+            break;
+        }
+        tags.push(AnlzTag {
+            fourcc: &bytes[pos..pos + 4],
+            len_header,
+            data: bytes.get(pos..pos + len_tag)?,
+        });
+        pos += len_tag;
+    }
+    return Some(tags);
+}
+
+fn parse_path_tag(tag: &AnlzTag) -> Option<String> {
+    let len_path = read_u32(tag.data, 12)? as usize;
+    return Some(read_utf16_be(tag.data.get(16..16 + len_path)?));
+}
+
+// Beat grid entries are (beat number, tempo * 100, time in ms); only the tempo
+// points are kept, in the same (seconds, bpm) form as the XML TEMPO elements.
+fn parse_beat_grid_tag(tag: &AnlzTag) -> Option<Vec<(f64, f64)>> {
+    let len_beats = read_u32(tag.data, 20)? as usize;
+    return (0..len_beats)
+        .map(|i| {
+            let entry = tag.len_header + i * 8;
+            Some((
+                read_u32(tag.data, entry + 4)? as f64 / 1000.,
+                read_u16(tag.data, entry + 2)? as f64 / 100.,
+            ))
+        })
+        .collect();
+}
+
+// Loop end times are 0xFFFFFFFF for plain cues.
+fn read_loop_end(bytes: &[u8], offset: usize) -> Option<f64> {
+    return read_u32(bytes, offset)
+        .filter(|loop_time| *loop_time != u32::MAX)
+        .map(|loop_time| loop_time as f64 / 1000.);
+}
+
+// PCOB cue lists (in .DAT files) carry positions only.
+fn parse_cue_tag(tag: &AnlzTag) -> Option<Vec<AnlzCue>> {
+    let len_cues = read_u16(tag.data, 18)? as usize;
+    let mut pos = tag.len_header;
+    let mut cues = Vec::new();
+    for _ in 0..len_cues {
+        let entry = tag.data.get(pos..)?;
+        let len_entry = read_u32(entry, 8)? as usize;
+        cues.push(AnlzCue {
+            seconds: read_u32(entry, 32)? as f64 / 1000.,
+            loop_end_seconds: read_loop_end(entry, 36),
+            hot_cue: read_u32(entry, 12)?,
+            is_loop: entry.get(28) == Some(&2),
+            comment: None,
+            color: None,
+        });
+        pos += len_entry;
+    }
+    return Some(cues);
+}
+
+// PCO2 cue lists (in .EXT files) additionally carry the cue comment and color.
+fn parse_extended_cue_tag(tag: &AnlzTag) -> Option<Vec<AnlzCue>> {
+    let len_cues = read_u16(tag.data, 16)? as usize;
+    let mut pos = tag.len_header;
+    let mut cues = Vec::new();
+    for _ in 0..len_cues {
+        let entry = tag.data.get(pos..)?;
+        let len_entry = read_u32(entry, 8)? as usize;
+        let len_comment = read_u32(entry, 40).unwrap_or(0) as usize;
+        let comment = entry
+            .get(44..44 + len_comment)
+            .map(read_utf16_be)
+            .filter(|comment| !comment.is_empty());
+        let color = entry
+            .get(45 + len_comment..48 + len_comment)
+            .filter(|rgb| entry[44 + len_comment] != 0 || rgb.iter().any(|c| *c != 0))
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]));
+        cues.push(AnlzCue {
+            seconds: read_u32(entry, 20)? as f64 / 1000.,
+            loop_end_seconds: read_loop_end(entry, 24),
+            hot_cue: read_u32(entry, 12)?,
+            is_loop: entry.get(16) == Some(&2),
+            comment,
+            color,
+        });
+        pos += len_entry;
+    }
+    return Some(cues);
+}
+
+fn read_anlz_file(path: &Path) -> Option<Vec<u8>> {
+    return std::fs::read(path).ok();
+}
+
+pub fn parse_anlz_analysis(dat_path: &Path) -> Option<AnlzAnalysis> {
+    let dat_bytes = read_anlz_file(dat_path)?;
+    let dat_tags = read_tags(&dat_bytes)?;
+    let ext_bytes = read_anlz_file(&dat_path.with_extension("EXT"))
+        .or_else(|| read_anlz_file(&dat_path.with_extension("ext")))
+        .unwrap_or_default();
+    let ext_tags = read_tags(&ext_bytes).unwrap_or_default();
+
+    let location = dat_tags
+        .iter()
+        .find(|tag| tag.fourcc == b"PPTH")
+        .and_then(parse_path_tag)?;
+    let tempo_points = dat_tags
+        .iter()
+        .find(|tag| tag.fourcc == b"PQTZ")
+        .and_then(parse_beat_grid_tag)
+        .unwrap_or_default();
+
+    let extended_cues: Vec<AnlzCue> = ext_tags
+        .iter()
+        .filter(|tag| tag.fourcc == b"PCO2")
+        .filter_map(parse_extended_cue_tag)
+        .flatten()
+        .collect();
+    let raw_cues = if extended_cues.is_empty() {
+        dat_tags
+            .iter()
+            .filter(|tag| tag.fourcc == b"PCOB")
+            .filter_map(parse_cue_tag)
+            .flatten()
+            .collect()
+    } else {
+        extended_cues
+    };
+
+    let mut cues: Vec<XmlCueInfo> = raw_cues
+        .into_iter()
+        .filter_map(|cue| {
+            Some(XmlCueInfo {
+                beat_offset: seconds_to_beats(cue.seconds, &tempo_points)?,
+                lighting: parse_lighting_cue(&cue.comment),
+                comment: cue.comment,
+                kind: if cue.is_loop { CueKind::Loop } else { CueKind::Cue },
+                hot_cue: cue.hot_cue.checked_sub(1).map(|slot| slot as u8),
+                loop_end_beat: cue
+                    .loop_end_seconds
+                    .and_then(|end| seconds_to_beats(end, &tempo_points)),
+                color: cue.color,
+            })
+        })
+        .collect();
+    cues.sort_by(|a, b| a.beat_offset.partial_cmp(&b.beat_offset).unwrap());
+    return Some(AnlzAnalysis { location, cues });
+}
+
+// ANLZ files carry no track ID, so tracks loaded from them alone have an ID of 0.
+pub fn parse_anlz_folder(path: &String) -> Result<Vec<XmlTrackInfo>, String> {
+    println!("loading rekordbox analysis files");
+    if !Path::new(path).is_dir() {
+        return Err(format!("analysis folder {} does not exist", path));
+    }
+    let tracks: Vec<XmlTrackInfo> = WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("DAT"))
+        })
+        .filter_map(|entry| parse_anlz_analysis(entry.path()))
+        .map(|analysis| XmlTrackInfo {
+            title: Path::new(&analysis.location)
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().to_string()),
+            artist: String::new(),
+            id: 0,
+            location: normalize_location(&analysis.location),
+            duration: None,
+            cues: analysis.cues,
+        })
+        .collect();
+    println!(
+        "Finished loading analysis files in {}, found {} tracks with {} cue points",
+        path,
+        tracks.len(),
+        tracks.iter().map(|track| track.cues.len()).sum::<usize>()
+    );
+    return Ok(tracks);
+}
+
+// Replaces the cues of collection tracks with the fresher ones from the analysis
+// files, matched by file location. Analysis-only tracks are appended.
+pub fn merge_anlz_cues(xml_tracks: &mut Vec<XmlTrackInfo>, anlz_tracks: Vec<XmlTrackInfo>) {
+    // location -> index of the first track there
+    let mut by_location: HashMap<String, usize> = HashMap::new();
+    for (i, xml_track) in xml_tracks.iter().enumerate() {
+        by_location.entry(xml_track.location.clone()).or_insert(i);
+    }
+    for anlz_track in anlz_tracks {
+        match by_location.get(&anlz_track.location) {
+            Some(i) => xml_tracks[*i].cues = anlz_track.cues,
+            None => {
+                by_location.insert(anlz_track.location.clone(), xml_tracks.len());
+                xml_tracks.push(anlz_track);
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rustc_hash::FxHasher;
+use std::error::Error;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::UNIX_EPOCH;
+
+use crate::rekordbox::{parse_lighting_cue, CueKind, XmlCueInfo, XmlTrackInfo};
+use crate::xml_stream::parse_rekordbox_xml_streaming;
+
+// The parsed collection is cached next to the XML as a gzipped binary file. The cache
+// is used as-is while the XML's modification time is unchanged; otherwise the XML's
+// content hash decides whether it has to be parsed again.
+
+const CACHE_MAGIC: &[u8; 4] = b"TUBC";
+// bump whenever the layout below changes
+const CACHE_VERSION: u32 = 1;
+const HASH_CHUNK_SIZE: u64 = 64 * 1024;
+
+struct CacheKey {
+    modified_nanos: u64,
+    content_hash: u64,
+}
+
+fn cache_path(collection_xml_path: &String) -> String {
+    return format!("{}.tubercache", collection_xml_path);
+}
+
+fn modified_nanos(path: &String) -> io::Result<u64> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    return Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64));
+}
+
+// Hashed in whole chunks, so the hash doesn't depend on how reads happen to be split and
+// the XML is never held in memory as a whole.
+fn content_hash(path: &String) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = FxHasher::default();
+    let mut chunk: Vec<u8> = Vec::with_capacity(HASH_CHUNK_SIZE as usize);
+    loop {
+        chunk.clear();
+        if (&mut reader).take(HASH_CHUNK_SIZE).read_to_end(&mut chunk)? == 0 {
+            break;
+        }
+        hasher.write(&chunk);
+    }
+    return Ok(hasher.finish());
+}
+
+fn write_string(writer: &mut dyn Write, value: &str) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(value.len() as u32)?;
+    return writer.write_all(value.as_bytes());
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; reader.read_u32::<LittleEndian>()? as usize];
+    reader.read_exact(&mut bytes)?;
+    return String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+}
+
+fn write_option<T>(
+    writer: &mut impl Write,
+    value: &Option<T>,
+    write_value: impl Fn(&mut dyn Write, &T) -> io::Result<()>,
+) -> io::Result<()> {
+    writer.write_u8(value.is_some() as u8)?;
+    if let Some(value) = value {
+        write_value(writer, value)?;
+    }
+    return Ok(());
+}
+
+fn read_option<R: Read, T>(
+    reader: &mut R,
+    read_value: impl Fn(&mut R) -> io::Result<T>,
+) -> io::Result<Option<T>> {
+    return match reader.read_u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(read_value(reader)?)),
+    };
+}
+
+fn cue_kind_to_byte(kind: CueKind) -> u8 {
+    return match kind {
+        CueKind::Cue => 0,
+        CueKind::FadeIn => 1,
+        CueKind::FadeOut => 2,
+        CueKind::Load => 3,
+        CueKind::Loop => 4,
+    };
+}
+
+fn cue_kind_from_byte(byte: u8) -> CueKind {
+    return match byte {
+        1 => CueKind::FadeIn,
+        2 => CueKind::FadeOut,
+        3 => CueKind::Load,
+        4 => CueKind::Loop,
+        _ => CueKind::Cue,
+    };
+}
+
+fn write_cue(writer: &mut impl Write, cue: &XmlCueInfo) -> io::Result<()> {
+    writer.write_f64::<LittleEndian>(cue.beat_offset)?;
+    write_option(writer, &cue.comment, |w, comment| write_string(w, comment))?;
+    writer.write_u8(cue_kind_to_byte(cue.kind))?;
+    write_option(writer, &cue.hot_cue, |w, hot_cue| w.write_u8(*hot_cue))?;
+    write_option(writer, &cue.loop_end_beat, |w, end| w.write_f64::<LittleEndian>(*end))?;
+    write_option(writer, &cue.color, |w, (red, green, blue)| w.write_all(&[*red, *green, *blue]))?;
+    return Ok(());
+}
+
+// Lighting directives are parsed again from the comment rather than stored.
+fn read_cue(reader: &mut impl Read) -> io::Result<XmlCueInfo> {
+    let beat_offset = reader.read_f64::<LittleEndian>()?;
+    let comment = read_option(reader, |r| read_string(r))?;
+    let kind = cue_kind_from_byte(reader.read_u8()?);
+    let hot_cue = read_option(reader, |r| r.read_u8())?;
+    let loop_end_beat = read_option(reader, |r| r.read_f64::<LittleEndian>())?;
+    let color = read_option(reader, |r| {
+        let mut rgb = [0; 3];
+        r.read_exact(&mut rgb)?;
+        Ok((rgb[0], rgb[1], rgb[2]))
+    })?;
+    return Ok(XmlCueInfo {
+        beat_offset,
+        lighting: parse_lighting_cue(&comment),
+        comment,
+        kind,
+        hot_cue,
+        loop_end_beat,
+        color,
+    });
+}
+
+fn write_cache(path: &String, key: &CacheKey, xml_tracks: &Vec<XmlTrackInfo>) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = GzEncoder::new(file, Compression::fast());
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_u32::<LittleEndian>(CACHE_VERSION)?;
+    writer.write_u64::<LittleEndian>(key.modified_nanos)?;
+    writer.write_u64::<LittleEndian>(key.content_hash)?;
+    writer.write_u32::<LittleEndian>(xml_tracks.len() as u32)?;
+    for track in xml_tracks.iter() {
+        write_string(&mut writer, &track.title)?;
+        write_string(&mut writer, &track.artist)?;
+        writer.write_u32::<LittleEndian>(track.id)?;
+        write_string(&mut writer, &track.location)?;
+        write_option(&mut writer, &track.duration, |w, duration| {
+            w.write_f64::<LittleEndian>(*duration)
+        })?;
+        writer.write_u32::<LittleEndian>(track.cues.len() as u32)?;
+        for cue in track.cues.iter() {
+            write_cue(&mut writer, cue)?;
+        }
+    }
+    writer.finish()?.flush()?;
+    return Ok(());
+}
+
+fn read_cache_key(reader: &mut impl Read) -> io::Result<CacheKey> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC || reader.read_u32::<LittleEndian>()? != CACHE_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a current collection cache"));
+    }
+    return Ok(CacheKey {
+        modified_nanos: reader.read_u64::<LittleEndian>()?,
+        content_hash: reader.read_u64::<LittleEndian>()?,
+    });
+}
+
+fn read_cache_tracks(reader: &mut impl Read) -> io::Result<Vec<XmlTrackInfo>> {
+    let track_count = reader.read_u32::<LittleEndian>()? as usize;
+    let mut xml_tracks = Vec::with_capacity(track_count);
+    for _ in 0..track_count {
+        let title = read_string(reader)?;
+        let artist = read_string(reader)?;
+        let id = reader.read_u32::<LittleEndian>()?;
+        let location = read_string(reader)?;
+        let duration = read_option(reader, |r| r.read_f64::<LittleEndian>())?;
+        let cue_count = reader.read_u32::<LittleEndian>()? as usize;
+        let cues = (0..cue_count)
+            .map(|_| read_cue(reader))
+            .collect::<io::Result<Vec<XmlCueInfo>>>()?;
+        xml_tracks.push(XmlTrackInfo {
+            title,
+            artist,
+            id,
+            location,
+            duration,
+            cues,
+        });
+    }
+    return Ok(xml_tracks);
+}
+
+// Also returns whether the cache's modification time is current. The XML is only hashed
+// when it isn't, and the hash is kept in `xml_hash` for the new cache key.
+fn try_load_cache(
+    path: &String,
+    xml_path: &String,
+    modified_nanos: u64,
+    xml_hash: &mut Option<u64>,
+) -> Option<(Vec<XmlTrackInfo>, bool)> {
+    let mut reader = GzDecoder::new(BufReader::new(File::open(path).ok()?));
+    let key = read_cache_key(&mut reader).ok()?;
+    let up_to_date = key.modified_nanos == modified_nanos;
+    if !up_to_date {
+        *xml_hash = content_hash(xml_path).ok();
+        if *xml_hash != Some(key.content_hash) {
+            return None;
+        }
+    }
+    return Some((read_cache_tracks(&mut reader).ok()?, up_to_date));
+}
+
+// Loads the collection from its cache when the XML hasn't changed, and parses the XML
+// and rewrites the cache when it has.
+pub fn load_cached_collection(collection_xml_path: &String) -> Result<Vec<XmlTrackInfo>, Box<dyn Error>> {
+    let path = cache_path(collection_xml_path);
+    let modified_nanos = modified_nanos(collection_xml_path).unwrap_or(0);
+    let mut xml_hash = None;
+    let cached = try_load_cache(&path, collection_xml_path, modified_nanos, &mut xml_hash);
+    if let Some((xml_tracks, true)) = cached {
+        println!("loaded {} tracks from collection cache {}", xml_tracks.len(), path);
+        return Ok(xml_tracks);
+    }
+
+    // a touched but unchanged XML only needs the cache key updated
+    let xml_tracks = match cached {
+        Some((xml_tracks, _)) => xml_tracks,
+        None => parse_rekordbox_xml_streaming(collection_xml_path)?,
+    };
+    let key = CacheKey {
+        modified_nanos,
+        content_hash: match xml_hash {
+            Some(xml_hash) => xml_hash,
+            None => content_hash(collection_xml_path).unwrap_or(0),
+        },
+    };
+    match write_cache(&path, &key, &xml_tracks) {
+        Ok(()) => println!("wrote collection cache {}", path),
+        Err(error) => println!("could not write collection cache {}: {}", path, error),
+    }
+    return Ok(xml_tracks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let file_name = format!("rust_tuber_{}_{}", std::process::id(), name);
+        return std::env::temp_dir().join(file_name).to_string_lossy().to_string();
+    }
+
+    fn cue(beat_offset: f64, comment: Option<&str>, kind: CueKind) -> XmlCueInfo {
+        let comment = comment.map(str::to_string);
+        return XmlCueInfo {
+            beat_offset,
+            lighting: parse_lighting_cue(&comment),
+            comment,
+            kind,
+            hot_cue: None,
+            loop_end_beat: None,
+            color: None,
+        };
+    }
+
+    fn collection() -> Vec<XmlTrackInfo> {
+        let mut looped = cue(32., Some("EW:show=strobe fade=1b transition=crossfade:2b"), CueKind::Loop);
+        looped.hot_cue = Some(2);
+        looped.loop_end_beat = Some(48.);
+        looped.color = Some((255, 128, 0));
+        return vec![
+            XmlTrackInfo {
+                title: "Atlas (Original Mix)".to_string(),
+                artist: "Someone & Friends".to_string(),
+                id: 7,
+                location: "c:/music/atlas.mp3".to_string(),
+                duration: Some(312.),
+                cues: vec![cue(0., None, CueKind::Cue), looped, cue(64., Some("Drop"), CueKind::FadeOut)],
+            },
+            XmlTrackInfo {
+                title: "Tëst".to_string(),
+                artist: String::new(),
+                id: 0,
+                location: String::new(),
+                duration: None,
+                cues: Vec::new(),
+            },
+        ];
+    }
+
+    #[test]
+    fn round_trip() {
+        let xml_path = temp_path("round_trip.xml");
+        std::fs::write(&xml_path, "<DJ_PLAYLISTS/>").unwrap();
+        let path = cache_path(&xml_path);
+        let xml_tracks = collection();
+        let key = CacheKey { modified_nanos: 42, content_hash: content_hash(&xml_path).unwrap() };
+        write_cache(&path, &key, &xml_tracks).unwrap();
+
+        let mut xml_hash = None;
+        let (cached, up_to_date) = try_load_cache(&path, &xml_path, 42, &mut xml_hash).unwrap();
+        assert!(up_to_date);
+        assert_eq!(xml_hash, None);
+        assert_eq!(format!("{:?}", cached), format!("{:?}", xml_tracks));
+
+        // touched but unchanged: still used, and the hash is kept for the new key
+        let (cached, up_to_date) = try_load_cache(&path, &xml_path, 43, &mut xml_hash).unwrap();
+        assert!(!up_to_date);
+        assert_eq!(xml_hash, Some(key.content_hash));
+        assert_eq!(format!("{:?}", cached), format!("{:?}", xml_tracks));
+
+        std::fs::write(&xml_path, "<DJ_PLAYLISTS></DJ_PLAYLISTS>").unwrap();
+        assert!(try_load_cache(&path, &xml_path, 44, &mut xml_hash).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&xml_path).unwrap();
+    }
+
+    #[test]
+    fn not_a_cache() {
+        let path = temp_path("not_a_cache.tubercache");
+        std::fs::write(&path, "<DJ_PLAYLISTS/>").unwrap();
+        assert!(try_load_cache(&path, &path, 0, &mut None).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hash_spans_chunks() {
+        let path = temp_path("hash.xml");
+        let mut content = vec![b'a'; HASH_CHUNK_SIZE as usize * 2 + 10];
+        std::fs::write(&path, &content).unwrap();
+        let hash = content_hash(&path).unwrap();
+        assert_eq!(content_hash(&path).unwrap(), hash);
+        content[HASH_CHUNK_SIZE as usize + 1] = b'b';
+        std::fs::write(&path, &content).unwrap();
+        assert_ne!(content_hash(&path).unwrap(), hash);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
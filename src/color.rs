@@ -0,0 +1,146 @@
+// Color fixtures are given their color as red, green and blue levels in the frame
+// ("wash.red", "wash.green", "wash.blue"), whatever channels the fixture really has.
+// The patch turns that color into the channels of the fixture's layout, found from its
+// type: "rgb", "rgbw", "rgba" or "hsi".
+
+pub const COLOR_CHANNELS: [&str; 3] = ["red", "green", "blue"];
+
+// Green in amber light, for each part of red.
+const AMBER_GREEN: f64 = 0.75;
+
+#[derive(Clone, Copy)]
+pub enum ColorLayout {
+    Rgb,
+    // white takes the part of the color that red, green and blue have in common
+    Rgbw,
+    // amber takes the part of the color that red and AMBER_GREEN as much green make
+    Rgba,
+    // hue (0 to 1 round the color wheel from red), saturation and intensity
+    Hsi,
+}
+
+impl ColorLayout {
+    // From a fixture's type; None for fixtures without color.
+    pub fn from_kind(kind: &str) -> Option<ColorLayout> {
+        return match kind.to_lowercase().as_str() {
+            "rgb" => Some(ColorLayout::Rgb),
+            "rgbw" => Some(ColorLayout::Rgbw),
+            "rgba" => Some(ColorLayout::Rgba),
+            "hsi" => Some(ColorLayout::Hsi),
+            _ => None,
+        };
+    }
+
+    // The fixture channels the layout drives, named as in the patch.
+    pub fn channels(&self) -> &'static [&'static str] {
+        return match self {
+            ColorLayout::Rgb => &["red", "green", "blue"],
+            ColorLayout::Rgbw => &["red", "green", "blue", "white"],
+            ColorLayout::Rgba => &["red", "green", "blue", "amber"],
+            ColorLayout::Hsi => &["hue", "saturation", "intensity"],
+        };
+    }
+
+    // The level of each of channels() for a color given as red, green and blue levels.
+    pub fn levels(&self, color: [f64; 3]) -> Vec<f64> {
+        let [red, green, blue] = color.map(|level| level.clamp(0., 1.));
+        return match self {
+            ColorLayout::Rgb => vec![red, green, blue],
+            ColorLayout::Rgbw => {
+                let white = red.min(green).min(blue);
+                vec![red - white, green - white, blue - white, white]
+            }
+            ColorLayout::Rgba => {
+                let amber = red.min(green / AMBER_GREEN);
+                vec![red - amber, green - amber * AMBER_GREEN, blue, amber]
+            }
+            ColorLayout::Hsi => {
+                let max = red.max(green).max(blue);
+                let chroma = max - red.min(green).min(blue);
+                let sector = if chroma == 0. {
+                    0.
+                } else if max == red {
+                    ((green - blue) / chroma).rem_euclid(6.)
+                } else if max == green {
+                    (blue - red) / chroma + 2.
+                } else {
+                    (red - green) / chroma + 4.
+                };
+                let saturation = if max > 0. { chroma / max } else { 0. };
+                vec![sector / 6., saturation, max]
+            }
+        };
+    }
+}
+
+// "#rrggbb" as red, green and blue levels from 0 to 1.
+pub fn parse_hex_color(color: &str) -> Option<[f64; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let mut levels = [0.; 3];
+    for (i, level) in levels.iter_mut().enumerate() {
+        *level = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()? as f64 / 255.;
+    }
+    return Some(levels);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_levels(levels: Vec<f64>, expected: &[f64]) {
+        assert_eq!(levels.len(), expected.len(), "{:?} != {:?}", levels, expected);
+        for (level, expected_level) in levels.iter().zip(expected) {
+            assert!((level - expected_level).abs() < 1e-9, "{:?} != {:?}", levels, expected);
+        }
+    }
+
+    #[test]
+    fn rgb_is_clamped() {
+        assert_levels(ColorLayout::Rgb.levels([0.2, 1.5, -0.5]), &[0.2, 1., 0.]);
+    }
+
+    #[test]
+    fn rgbw_takes_white_from_the_common_part() {
+        assert_levels(ColorLayout::Rgbw.levels([1., 1., 1.]), &[0., 0., 0., 1.]);
+        assert_levels(ColorLayout::Rgbw.levels([1., 0.6, 0.2]), &[0.8, 0.4, 0., 0.2]);
+        assert_levels(ColorLayout::Rgbw.levels([0., 0.5, 1.]), &[0., 0.5, 1., 0.]);
+    }
+
+    #[test]
+    fn rgba_takes_amber_from_red_and_green() {
+        assert_levels(ColorLayout::Rgba.levels([1., 0.75, 0.]), &[0., 0., 0., 1.]);
+        assert_levels(ColorLayout::Rgba.levels([1., 0.375, 0.5]), &[0.5, 0., 0.5, 0.5]);
+        assert_levels(ColorLayout::Rgba.levels([0.2, 1., 0.]), &[0., 0.85, 0., 0.2]);
+        assert_levels(ColorLayout::Rgba.levels([0., 0., 1.]), &[0., 0., 1., 0.]);
+    }
+
+    #[test]
+    fn hsi_hue_sectors() {
+        assert_levels(ColorLayout::Hsi.levels([1., 0., 0.]), &[0., 1., 1.]);
+        assert_levels(ColorLayout::Hsi.levels([1., 1., 0.]), &[1. / 6., 1., 1.]);
+        assert_levels(ColorLayout::Hsi.levels([0., 1., 0.]), &[2. / 6., 1., 1.]);
+        assert_levels(ColorLayout::Hsi.levels([0., 0., 1.]), &[4. / 6., 1., 1.]);
+        assert_levels(ColorLayout::Hsi.levels([1., 0., 0.5]), &[11. / 12., 1., 1.]);
+        assert_levels(ColorLayout::Hsi.levels([0.5, 0.25, 0.25]), &[0., 0.5, 0.5]);
+    }
+
+    #[test]
+    fn hsi_without_chroma() {
+        assert_levels(ColorLayout::Hsi.levels([0.4, 0.4, 0.4]), &[0., 0., 0.4]);
+        assert_levels(ColorLayout::Hsi.levels([0., 0., 0.]), &[0., 0., 0.]);
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex_color("#ff0000"), Some([1., 0., 0.]));
+        assert_eq!(parse_hex_color("#00FF33"), Some([0., 1., 0.2]));
+        assert_eq!(parse_hex_color("ff0000"), None);
+        assert_eq!(parse_hex_color("#ff00"), None);
+        assert_eq!(parse_hex_color("#ff00000"), None);
+        assert_eq!(parse_hex_color("#gg0000"), None);
+        assert_eq!(parse_hex_color("#ff00é"), None);
+    }
+}
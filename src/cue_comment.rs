@@ -0,0 +1,308 @@
+use std::fmt;
+
+// Lighting cues are memory/hot cues whose comment starts with "EW", e.g.
+//   EW:show=strobe intensity=0.7 fade=2b transition=crossfade:1bar
+// The older "EWstrobe" form is still accepted and only names the show.
+
+const LIGHTING_PREFIX: &str = "EW";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CueDirective {
+    Show(String),
+    Intensity(f64),
+    // fade in over this many beats
+    Fade(f64),
+    // how the show takes over from the previous lighting cue's show
+    Transition(Transition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Cut,
+    // blend from the previous show over this many beats
+    Crossfade(f64),
+    // switch fixtures over one by one, in patch order, over this many beats
+    Wipe(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CueParseError {
+    MissingShow,
+    MissingValue(String),
+    UnknownKey(String),
+    InvalidNumber(String, String),
+    DuplicateKey(String),
+}
+
+impl fmt::Display for CueParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CueParseError::MissingShow => write!(f, "no show name given"),
+            CueParseError::MissingValue(token) => write!(f, "'{}' is not a key=value pair", token),
+            CueParseError::UnknownKey(key) => write!(f, "unknown key '{}'", key),
+            CueParseError::InvalidNumber(key, value) => {
+                write!(f, "'{}' is not a valid value for {}", value, key)
+            }
+            CueParseError::DuplicateKey(key) => write!(f, "'{}' is given more than once", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightingCue {
+    pub directives: Vec<CueDirective>,
+}
+
+impl LightingCue {
+    pub fn show(&self) -> &str {
+        return self
+            .directives
+            .iter()
+            .find_map(|directive| match directive {
+                CueDirective::Show(show) => Some(show.as_str()),
+                _ => None,
+            })
+            .unwrap_or("");
+    }
+
+    pub fn intensity(&self) -> f64 {
+        return self
+            .directives
+            .iter()
+            .find_map(|directive| match directive {
+                CueDirective::Intensity(intensity) => Some(*intensity),
+                _ => None,
+            })
+            .unwrap_or(1.);
+    }
+
+    pub fn fade_beats(&self) -> Option<f64> {
+        return self.directives.iter().find_map(|directive| match directive {
+            CueDirective::Fade(beats) => Some(*beats),
+            _ => None,
+        });
+    }
+
+    pub fn transition(&self) -> Transition {
+        return self
+            .directives
+            .iter()
+            .find_map(|directive| match directive {
+                CueDirective::Transition(transition) => Some(*transition),
+                _ => None,
+            })
+            .unwrap_or(Transition::Cut);
+    }
+
+    // Weight of the cue's show at the given number of beats after the cue.
+    pub fn weight_at(&self, beats_since_cue: f64) -> f64 {
+        let fade = self
+            .fade_beats()
+            .filter(|beats| *beats > 0.)
+            .map_or(1., |beats| (beats_since_cue / beats).clamp(0., 1.));
+        return self.intensity() * fade;
+    }
+}
+
+// Lengths are beats unless suffixed: "2", "2b" and "0.5bar" are all valid.
+pub fn parse_beats(value: &str) -> Option<f64> {
+    let (number, beats_per_unit) = if let Some(number) = value.strip_suffix("bars") {
+        (number, 4.)
+    } else if let Some(number) = value.strip_suffix("bar") {
+        (number, 4.)
+    } else if let Some(number) = value.strip_suffix('b') {
+        (number, 1.)
+    } else {
+        (value, 1.)
+    };
+    return number
+        .parse::<f64>()
+        .ok()
+        .filter(|beats| beats.is_finite())
+        .map(|beats| beats * beats_per_unit);
+}
+
+// "cut", or "crossfade" / "wipe" with a length, e.g. "crossfade:2b".
+fn parse_transition(value: &str) -> Option<Transition> {
+    if value == "cut" {
+        return Some(Transition::Cut);
+    }
+    let (kind, length) = value.split_once(':')?;
+    let beats = parse_beats(length).filter(|beats| *beats > 0.)?;
+    return match kind {
+        "crossfade" => Some(Transition::Crossfade(beats)),
+        "wipe" => Some(Transition::Wipe(beats)),
+        _ => None,
+    };
+}
+
+fn parse_directive(key: &str, value: &str) -> Result<CueDirective, CueParseError> {
+    let invalid = || CueParseError::InvalidNumber(key.to_string(), value.to_string());
+    return match key {
+        "show" => Ok(CueDirective::Show(value.to_string())),
+        "intensity" => value
+            .parse::<f64>()
+            .ok()
+            .filter(|intensity| (0. ..=1.).contains(intensity))
+            .map(CueDirective::Intensity)
+            .ok_or_else(invalid),
+        "fade" => parse_beats(value)
+            .filter(|beats| *beats >= 0.)
+            .map(CueDirective::Fade)
+            .ok_or_else(invalid),
+        "transition" => parse_transition(value)
+            .map(CueDirective::Transition)
+            .ok_or_else(invalid),
+        _ => Err(CueParseError::UnknownKey(key.to_string())),
+    };
+}
+
+// Returns None when the comment does not mark a lighting cue at all.
+pub fn parse_cue_comment(comment: &str) -> Option<Result<LightingCue, CueParseError>> {
+    let body = comment.trim().strip_prefix(LIGHTING_PREFIX)?;
+    let body = match body.strip_prefix(':') {
+        Some(body) => body,
+        None => {
+            let show = body.trim();
+            if show.is_empty() {
+                return Some(Err(CueParseError::MissingShow));
+            }
+            return Some(Ok(LightingCue {
+                directives: vec![CueDirective::Show(show.to_string())],
+            }));
+        }
+    };
+
+    let mut directives: Vec<CueDirective> = Vec::new();
+    let mut keys: Vec<&str> = Vec::new();
+    for token in body.split_whitespace() {
+        let (key, value) = match token.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !value.is_empty() => (key, value),
+            _ => return Some(Err(CueParseError::MissingValue(token.to_string()))),
+        };
+        if keys.contains(&key) {
+            return Some(Err(CueParseError::DuplicateKey(key.to_string())));
+        }
+        keys.push(key);
+        match parse_directive(key, value) {
+            Ok(directive) => directives.push(directive),
+            Err(error) => return Some(Err(error)),
+        }
+    }
+    if !keys.contains(&"show") {
+        return Some(Err(CueParseError::MissingShow));
+    }
+    return Some(Ok(LightingCue { directives }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(comment: &str) -> Result<LightingCue, CueParseError> {
+        return parse_cue_comment(comment).expect("not a lighting cue");
+    }
+
+    #[test]
+    fn beats_with_units() {
+        assert_eq!(parse_beats("2"), Some(2.));
+        assert_eq!(parse_beats("2b"), Some(2.));
+        assert_eq!(parse_beats("0.5bar"), Some(2.));
+        assert_eq!(parse_beats("2bars"), Some(8.));
+        assert_eq!(parse_beats(""), None);
+        assert_eq!(parse_beats("bar"), None);
+        assert_eq!(parse_beats("twob"), None);
+        assert_eq!(parse_beats("infb"), None);
+    }
+
+    #[test]
+    fn transitions() {
+        assert_eq!(parse_transition("cut"), Some(Transition::Cut));
+        assert_eq!(
+            parse_transition("crossfade:1bar"),
+            Some(Transition::Crossfade(4.))
+        );
+        assert_eq!(parse_transition("wipe:2b"), Some(Transition::Wipe(2.)));
+        assert_eq!(parse_transition("crossfade"), None);
+        assert_eq!(parse_transition("crossfade:0"), None);
+        assert_eq!(parse_transition("crossfade:-1"), None);
+        assert_eq!(parse_transition("dissolve:2b"), None);
+    }
+
+    #[test]
+    fn not_a_lighting_cue() {
+        assert_eq!(parse_cue_comment(""), None);
+        assert_eq!(parse_cue_comment("drop here"), None);
+    }
+
+    #[test]
+    fn old_form_names_the_show() {
+        let cue = parse("EWstrobe").unwrap();
+        assert_eq!(cue.show(), "strobe");
+        assert_eq!(cue.intensity(), 1.);
+        assert_eq!(cue.fade_beats(), None);
+        assert_eq!(cue.transition(), Transition::Cut);
+        assert_eq!(parse("EW"), Err(CueParseError::MissingShow));
+    }
+
+    #[test]
+    fn key_value_form() {
+        let cue = parse("EW:show=strobe intensity=0.7 fade=2b transition=crossfade:1bar").unwrap();
+        assert_eq!(cue.show(), "strobe");
+        assert_eq!(cue.intensity(), 0.7);
+        assert_eq!(cue.fade_beats(), Some(2.));
+        assert_eq!(cue.transition(), Transition::Crossfade(4.));
+        assert_eq!(cue.weight_at(1.), 0.35);
+        assert_eq!(cue.weight_at(4.), 0.7);
+    }
+
+    #[test]
+    fn missing_keys() {
+        assert_eq!(parse("EW:"), Err(CueParseError::MissingShow));
+        assert_eq!(parse("EW:intensity=0.5"), Err(CueParseError::MissingShow));
+        let cue = parse("EW:show=wash").unwrap();
+        assert_eq!(cue.intensity(), 1.);
+        assert_eq!(cue.weight_at(0.), 1.);
+    }
+
+    #[test]
+    fn malformed_comments() {
+        assert_eq!(
+            parse("EW:show"),
+            Err(CueParseError::MissingValue("show".to_string()))
+        );
+        assert_eq!(
+            parse("EW:show="),
+            Err(CueParseError::MissingValue("show=".to_string()))
+        );
+        assert_eq!(
+            parse("EW:show=wash colour=red"),
+            Err(CueParseError::UnknownKey("colour".to_string()))
+        );
+        assert_eq!(
+            parse("EW:show=wash intensity=1.5"),
+            Err(CueParseError::InvalidNumber(
+                "intensity".to_string(),
+                "1.5".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("EW:show=wash fade=-2"),
+            Err(CueParseError::InvalidNumber(
+                "fade".to_string(),
+                "-2".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("EW:show=wash transition=wipe"),
+            Err(CueParseError::InvalidNumber(
+                "transition".to_string(),
+                "wipe".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("EW:show=wash show=strobe"),
+            Err(CueParseError::DuplicateKey("show".to_string()))
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use roxmltree::{Document, Node};
+use std::error::Error;
+
+use crate::rekordbox::{beats_to_seconds, parse_xml_tempo_points};
+
+// New cues are spliced into the original text rather than re-serializing the
+// document, so everything rekordbox doesn't need to see changed stays byte-identical.
+
+fn escape_attribute(value: &str) -> String {
+    return value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+// None when the node doesn't start its own line.
+fn indentation_of(raw_xml: &str, node: Node) -> Option<String> {
+    let start = node.range().start;
+    let line_start = raw_xml[..start].rfind('\n').map_or(0, |i| i + 1);
+    return Some(raw_xml[line_start..start].to_string())
+        .filter(|indent| indent.chars().all(char::is_whitespace));
+}
+
+fn find_track<'a, 'input>(doc: &'a Document<'input>, track_id: u32) -> Option<Node<'a, 'input>> {
+    let collection = doc
+        .root()
+        .descendants()
+        .find(|n| n.has_tag_name("COLLECTION"))?;
+    return collection.children().find(|n| {
+        n.has_tag_name("TRACK")
+            && n.attribute("TrackID").and_then(|id| id.parse::<u32>().ok()) == Some(track_id)
+    });
+}
+
+// Writes a copy of the collection XML with a memory cue named "EW:show=<show>"
+// added to the track at each (beat, show) placement.
+pub fn write_lighting_cues(
+    collection_xml_path: &String,
+    output_path: &String,
+    track_id: u32,
+    placements: &Vec<(f64, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let raw_xml = std::fs::read_to_string(collection_xml_path)?;
+    let doc = Document::parse(&raw_xml)?;
+    let track_elem = find_track(&doc, track_id)
+        .ok_or_else(|| format!("track {} is not in {}", track_id, collection_xml_path))?;
+    let tempo_points = parse_xml_tempo_points(track_elem);
+
+    let track_indent = indentation_of(&raw_xml, track_elem).unwrap_or_default();
+    let mark_indent = track_elem
+        .children()
+        .find(|child| child.is_element())
+        .and_then(|child| indentation_of(&raw_xml, child))
+        .unwrap_or(format!("{}  ", track_indent));
+
+    let mut marks = String::new();
+    for (beat, show) in placements {
+        if show.is_empty() || show.contains(char::is_whitespace) {
+            return Err(format!("'{}' is not a valid show name", show).into());
+        }
+        let seconds = beats_to_seconds(*beat, &tempo_points)
+            .ok_or_else(|| format!("track {} has no beat grid", track_id))?;
+        if seconds < 0. {
+            return Err(format!("beat {} is before the start of track {}", beat, track_id).into());
+        }
+        marks.push_str(&format!(
+            "{}<POSITION_MARK Name=\"{}\" Type=\"0\" Start=\"{:.3}\" Num=\"-1\"/>\n",
+            mark_indent,
+            escape_attribute(&format!("EW:show={}", show)),
+            seconds
+        ));
+    }
+
+    let range = track_elem.range();
+    let track_xml = &raw_xml[range.clone()];
+    let new_track_xml = match track_xml.strip_suffix("/>") {
+        Some(opening) => format!("{}>\n{}{}</TRACK>", opening, marks, track_indent),
+        None => {
+            let closing_start = track_xml
+                .rfind("</")
+                .expect("could not find closing track tag");
+            let insert_at = track_xml[..closing_start].rfind('\n').map_or(closing_start, |i| i + 1);
+            format!("{}{}{}", &track_xml[..insert_at], marks, &track_xml[insert_at..])
+        }
+    };
+
+    let output = format!(
+        "{}{}{}",
+        &raw_xml[..range.start],
+        new_track_xml,
+        &raw_xml[range.end..]
+    );
+    std::fs::write(output_path, output)?;
+    println!(
+        "wrote {} lighting cues for track {} to {}",
+        placements.len(),
+        track_id,
+        output_path
+    );
+    return Ok(());
+}
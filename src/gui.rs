@@ -6,8 +6,9 @@ use std::{
 };
 
 use colored::Colorize;
+use itertools::Itertools;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -16,6 +17,11 @@ use ratatui::{
     widgets::{block::title, *},
 };
 
+use crate::output::LightingOutput;
+use crate::overrides::Overrides;
+use crate::rekordbox::{RekordboxAccess, RekordboxUpdate, TrackState};
+use crate::shows::{DeckMixer, DeckShow, MixerState, PreviousShow, ShowsManager};
+
 type Frame<'a> = ratatui::Frame<'a, CrosstermBackend<Stdout>>;
 
 pub struct Tuber {
@@ -23,20 +29,30 @@ pub struct Tuber {
     rekordbox_access: RekordboxAccess,
     shows_manager: ShowsManager,
     output: Box<dyn LightingOutput>,
+    // the operator's overrides, applied to every frame before it is output
+    overrides: Overrides,
+    // (track ID, display name) of the chosen set playlist, in order
+    set_playlist: Vec<(u32, String)>,
     terminal: Terminal<CrosstermBackend<Stdout>>,
 }
 
+const UPCOMING_TRACKS: usize = 5;
+
 impl Tuber {
     pub fn create(
         shows_manager: ShowsManager,
         rekordbox_access: RekordboxAccess,
         output: Box<dyn LightingOutput>,
+        overrides: Overrides,
+        set_playlist: Vec<(u32, String)>,
     ) -> Result<Tuber, Box<dyn Error>> {
         let mut terminal = Tuber::setup_terminal()?;
         Ok(Tuber {
             shows_manager,
             rekordbox_access,
             output,
+            overrides,
+            set_playlist,
             terminal,
         })
     }
@@ -50,17 +66,55 @@ impl Tuber {
         f.render_widget(
             Paragraph::new(format!(
                 "Track {} @ {:.3}
+Matched by: {}
 Current Cue: {:?}",
-                track.id, track.beat_offset, track.last_cue
+                track.id, track.beat_offset, track.match_confidence, track.last_cue
             ))
             .block(block),
             area,
         );
     }
 
-    fn draw_frame(f: &mut Frame, area: Rect, frame: &HashMap<String, f64>) {
+    // Lists the playlist tracks after the furthest one loaded on either deck.
+    fn draw_upcoming(
+        f: &mut Frame,
+        area: Rect,
+        set_playlist: &Vec<(u32, String)>,
+        rekordbox_update: &RekordboxUpdate,
+    ) {
         let block = Block::default()
-            .title("Frame")
+            .title("Up Next")
+            .borders(Borders::ALL)
+            .padding(Padding::uniform(1));
+        let position = [&rekordbox_update.track_1, &rekordbox_update.track_2]
+            .iter()
+            .filter_map(|track| {
+                let matched_id = track.matched_id?;
+                set_playlist.iter().position(|(id, _)| *id == matched_id)
+            })
+            .max();
+        let text = set_playlist
+            .iter()
+            .enumerate()
+            .skip(position.map_or(0, |position| position + 1))
+            .take(UPCOMING_TRACKS)
+            .map(|(i, (_, name))| format!("{}. {}", i + 1, name))
+            .join("\n");
+        f.render_widget(Paragraph::new(text).block(block), area);
+    }
+
+    fn draw_frame(
+        f: &mut Frame,
+        area: Rect,
+        frame: &HashMap<String, f64>,
+        overrides: &Overrides,
+    ) {
+        let title = match overrides.status() {
+            Some(status) => format!("Frame ({})", status),
+            None => "Frame".to_string(),
+        };
+        let block = Block::default()
+            .title(title)
             .borders(Borders::ALL)
             .padding(Padding::uniform(1));
         // f.render_widget(block, area);
@@ -77,9 +131,23 @@ Current Cue: {:?}",
                 row.into_iter().join(" | ")
             })
             .join("\n");
+        let text = format!("{}\n\n{}", overrides.keys(), text);
         f.render_widget(Paragraph::new(text).block(block), area);
     }
 
+    fn draw_errors(f: &mut Frame, area: Rect, errors: &Vec<String>) {
+        let block = Block::default()
+            .title("Errors")
+            .borders(Borders::ALL);
+        f.render_widget(
+            Paragraph::new(errors.join("\n"))
+                .style(Style::default().fg(Color::Red))
+                .wrap(Wrap { trim: true })
+                .block(block),
+            area,
+        );
+    }
+
     fn ui(
         &mut self,
         rekordbox_update: RekordboxUpdate,
@@ -100,27 +168,85 @@ Current Cue: {:?}",
 
             let left_track = cols[0];
             let right_track = cols[1];
-            let both = rows[1];
+            let both = if self.set_playlist.is_empty() {
+                rows[1]
+            } else {
+                let bottom = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                    .split(rows[1]);
+                Self::draw_upcoming(f, bottom[1], &self.set_playlist, &rekordbox_update);
+                bottom[0]
+            };
+
+            // an output that can't connect and shows that failed to (re)load are listed
+            // above the frame
+            let mut errors: Vec<String> = self.output.status().into_iter().collect();
+            errors.extend(self.shows_manager.errors());
+            let both = if errors.is_empty() {
+                both
+            } else {
+                let split = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(
+                        [Constraint::Length(errors.len() as u16 + 2), Constraint::Min(0)].as_ref(),
+                    )
+                    .split(both);
+                Self::draw_errors(f, split[0], &errors);
+                split[1]
+            };
 
             Self::ui_track(f, left_track, rekordbox_update.track_1, "LEFT TRACK");
             Self::ui_track(f, right_track, rekordbox_update.track_2, "RIGHT TRACK");
-            Self::draw_frame(f, both, frame);
+            Self::draw_frame(f, both, frame, &self.overrides);
         })?;
         Ok(())
     }
 
     fn get_frame(&mut self, rekordbox_update: &RekordboxUpdate) -> HashMap<String, f64> {
-        let mut states: Vec<(String, f64, f64)> = Vec::new();
-        for track in vec![&rekordbox_update.track_1, &rekordbox_update.track_2] {
-            if let Some(last_cue) = &track.last_cue {
-                states.push((
-                    last_cue.comment.clone().unwrap()[2..].to_string(),
-                    track.beat_offset - last_cue.beat_offset,
-                    1.,
-                ));
+        let faders = &rekordbox_update.faders;
+        // deck 1 is on the crossfader's left (0), deck 2 on its right (1)
+        let decks = [
+            (&rekordbox_update.track_1, faders.track_1_fader, 1. - faders.crossfader),
+            (&rekordbox_update.track_2, faders.track_2_fader, faders.crossfader),
+        ];
+        let mut states: Vec<DeckShow> = Vec::new();
+        let mut mixer = MixerState {
+            decks: Vec::new(),
+            crossfader: faders.crossfader as f64,
+        };
+        for (i, (track, fader, crossfader)) in decks.into_iter().enumerate() {
+            mixer.decks.push(DeckMixer {
+                beat_offset: track.beat_offset,
+                gain: fader as f64 * (2. * crossfader as f64).min(1.),
+            });
+            let last_cue = track.last_cue.as_ref();
+            if let Some(lighting) = last_cue.and_then(|cue| cue.lighting.as_ref()) {
+                let beats_since_cue = track.beat_offset - last_cue.unwrap().beat_offset;
+                states.push(DeckShow {
+                    title: lighting.show().to_string(),
+                    deck: i + 1,
+                    cue: last_cue.unwrap().clone(),
+                    beats: beats_since_cue,
+                    cue_weight: lighting.weight_at(beats_since_cue),
+                    fader: fader as f64,
+                    crossfader: crossfader as f64,
+                    transition: lighting.transition(),
+                    previous: track.previous_cue.as_ref().and_then(|cue| {
+                        let lighting = cue.lighting.as_ref()?;
+                        let beats_since_cue = track.beat_offset - cue.beat_offset;
+                        Some(PreviousShow {
+                            title: lighting.show().to_string(),
+                            cue: cue.clone(),
+                            beats: beats_since_cue,
+                            cue_weight: lighting.weight_at(beats_since_cue),
+                        })
+                    }),
+                });
             }
         }
-        let frame = self.shows_manager.get_combined_frame(states);
+        self.shows_manager.advance_idle(&mixer, &states);
+        let frame = self.shows_manager.get_combined_frame(states, &mixer);
         frame
     }
 
@@ -136,6 +262,7 @@ Current Cue: {:?}",
         refresh_ui: bool,
     ) -> Result<(), Box<dyn Error>> {
         // let delay = time::Duration::from_micros(2);
+        self.shows_manager.reload_changed();
         if let Some(rekordbox_update) = self.rekordbox_access.get_update() {
             // println!(
             //     "cueLeft: {:?} {:?} {}",
@@ -144,7 +271,7 @@ Current Cue: {:?}",
             // thread::sleep(time::Duration::from_millis(20));
             // println!("{:?} {:?}", rekordbox_update.track_1, rekordbox_update.track_2);
             let frame = self.get_frame(&rekordbox_update);
-            
+            let frame = self.overrides.apply(frame);
             self.output.write_frame_mapped(&frame);
             if (refresh_ui) {
                 self.ui(rekordbox_update, &frame)?;
@@ -192,6 +319,10 @@ Current Cue: {:?}",
             //     serial_output.write_frame(&frame.frame);
             //     return levels_to_graph(&frame.frame);
             // });
+        } else if let Some(frame) = self.overrides.reapply() {
+            // the shows hold their last frame while rekordbox is detached, but overrides
+            // like blackout still have to reach the rig
+            self.output.write_frame_mapped(&frame);
         }
         Ok(())
     }
@@ -208,6 +339,10 @@ Current Cue: {:?}",
                     if KeyCode::Char('q') == key.code {
                         break;
                     }
+                    // key releases are reported too on Windows and would toggle twice
+                    if key.kind == KeyEventKind::Press {
+                        self.overrides.handle_key(key.code);
+                    }
                 }
             }
             i += 1;
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::cue_comment::parse_cue_comment;
+use crate::rekordbox::XmlTrackInfo;
+
+// Lighting cues closer together than this are reported as duplicates.
+const DUPLICATE_CUE_BEATS: f64 = 0.05;
+
+// Show titles from a shows manifest ({"shows": [{"title": ...}, ...]}).
+pub fn read_show_names(shows_json_path: &String) -> Result<HashSet<String>, Box<dyn Error>> {
+    let json_content = std::fs::read_to_string(shows_json_path)?;
+    let json: serde_json::Value = serde_json::from_str(&json_content)?;
+    return Ok(json["shows"]
+        .as_array()
+        .ok_or("shows manifest has no shows list")?
+        .iter()
+        .filter_map(|show| show["title"].as_str())
+        .map(str::to_string)
+        .collect());
+}
+
+fn lint_track(track: &XmlTrackInfo, show_names: &HashSet<String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut lighting_beats: Vec<f64> = Vec::new();
+    for cue in track.cues.iter() {
+        let comment = match &cue.comment {
+            Some(comment) => comment,
+            None => continue,
+        };
+        let lighting = match parse_cue_comment(comment) {
+            None => continue,
+            Some(Err(error)) => {
+                problems.push(format!("malformed cue '{}' at beat {:.2}: {}", comment, cue.beat_offset, error));
+                continue;
+            }
+            Some(Ok(lighting)) => lighting,
+        };
+        if !show_names.contains(lighting.show()) {
+            problems.push(format!("unknown show '{}' at beat {:.2}", lighting.show(), cue.beat_offset));
+        }
+        if cue.beat_offset < 0. {
+            problems.push(format!("cue '{}' is before the first beat ({:.2})", comment, cue.beat_offset));
+        }
+        if lighting_beats
+            .iter()
+            .any(|beat| (beat - cue.beat_offset).abs() < DUPLICATE_CUE_BEATS)
+        {
+            problems.push(format!("more than one lighting cue at beat {:.2}", cue.beat_offset));
+        }
+        lighting_beats.push(cue.beat_offset);
+    }
+    return problems;
+}
+
+fn has_lighting_cues(track: &XmlTrackInfo) -> bool {
+    return track.cues.iter().any(|cue| cue.lighting.is_some());
+}
+
+// Prints every problem found and returns how many there were.
+pub fn lint_collection(
+    xml_tracks: &Vec<XmlTrackInfo>,
+    show_names: &HashSet<String>,
+    playlist_track_ids: &HashSet<u32>,
+) -> usize {
+    let mut problem_count = 0;
+    for track in xml_tracks.iter() {
+        let mut problems = lint_track(track, show_names);
+        if playlist_track_ids.contains(&track.id) && !has_lighting_cues(track) {
+            problems.push("in a playlist but has no lighting cues".to_string());
+        }
+        for problem in problems.iter() {
+            println!("{} '{}' - {}: {}", track.id, track.title, track.artist, problem);
+        }
+        problem_count += problems.len();
+    }
+    println!("{} problems found in {} tracks", problem_count, xml_tracks.len());
+    return problem_count;
+}
@@ -1,17 +1,196 @@
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
 
 mod rekordbox;
-use rekordbox::RekordboxAccess;
+use rekordbox::{RekordboxAccess, XmlTrackInfo};
+mod anlz;
+use anlz::{merge_anlz_cues, parse_anlz_folder};
+mod pdb;
+use pdb::parse_usb_export;
+mod color;
+mod cue_comment;
+use cue_comment::parse_beats;
+mod cue_writer;
+use cue_writer::write_lighting_cues;
 mod gui;
+mod output;
+use output::{LightingOutput, NoOutput, Pca9685Output, OUTPUTS_PER_BOARD};
+mod overrides;
+use overrides::Overrides;
+mod patch;
+use patch::Patch;
+mod procedural;
+mod scripted;
+mod shows;
+use shows::ShowsManager;
+mod lint;
+use lint::{lint_collection, read_show_names};
+mod track_matching;
+mod xml_stream;
+use xml_stream::{benchmark_xml_loaders, parse_rekordbox_playlists};
+mod collection_cache;
+use collection_cache::load_cached_collection;
 
 use crate::gui::Tuber;
 
+fn arg_value(args: &Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    return args.get(index + 1).cloned();
+}
+
+fn arg_values(args: &Vec<String>, name: &str) -> Vec<String> {
+    return args
+        .iter()
+        .zip(args.iter().skip(1))
+        .filter(|(arg, _)| *arg == name)
+        .map(|(_, value)| value.clone())
+        .collect();
+}
+
+// write-cues --collection-xml-path IN --output OUT --track-id ID --cue BEAT:SHOW [--cue ...]
+fn write_cues(args: &Vec<String>) -> Result<(), Box<dyn Error>> {
+    let collection_xml_path =
+        arg_value(args, "--collection-xml-path").ok_or("--collection-xml-path is required")?;
+    let output_path = arg_value(args, "--output").ok_or("--output is required")?;
+    let track_id = arg_value(args, "--track-id")
+        .and_then(|id| id.parse::<u32>().ok())
+        .ok_or("--track-id is required")?;
+    let placements = arg_values(args, "--cue")
+        .iter()
+        .map(|cue| {
+            let (beat, show) = cue.split_once(':').ok_or(format!("'{}' is not BEAT:SHOW", cue))?;
+            let beat = parse_beats(beat).ok_or(format!("'{}' is not a beat position", beat))?;
+            Ok((beat, show.to_string()))
+        })
+        .collect::<Result<Vec<(f64, String)>, String>>()?;
+    return write_lighting_cues(&collection_xml_path, &output_path, track_id, &placements);
+}
+
+// lint --collection-xml-path IN [--shows-path shows/shows.json] [--playlist NAME]
+fn lint(args: &Vec<String>) -> Result<(), Box<dyn Error>> {
+    let shows_path = arg_value(args, "--shows-path").unwrap_or("shows/shows.json".to_string());
+    let show_names = read_show_names(&shows_path)?;
+    let (xml_tracks, set_playlist) = select_playlist(args, load_collection(args)?)?;
+    // playlists name the XML's track IDs, which tracks from --usb-path don't have
+    let playlists_path = arg_value(args, "--collection-xml-path")
+        .filter(|_| arg_value(args, "--usb-path").is_none());
+    let playlist_track_ids: HashSet<u32> = match (set_playlist, playlists_path) {
+        (Some(set_playlist), _) => set_playlist.iter().map(|(id, _)| *id).collect(),
+        (None, Some(path)) => parse_rekordbox_playlists(&path, &xml_tracks)?
+            .iter()
+            .flat_map(|playlist| playlist.track_ids.clone())
+            .collect(),
+        (None, None) => HashSet::new(),
+    };
+    let problem_count = lint_collection(&xml_tracks, &show_names, &playlist_track_ids);
+    if problem_count > 0 {
+        return Err(format!("{} problems found", problem_count).into());
+    }
+    return Ok(());
+}
+
+fn load_collection(args: &Vec<String>) -> Result<Vec<XmlTrackInfo>, Box<dyn Error>> {
+    let mut xml_tracks = match (arg_value(args, "--usb-path"), arg_value(args, "--collection-xml-path")) {
+        (Some(usb_path), _) => parse_usb_export(&usb_path)?,
+        (None, Some(path)) => load_cached_collection(&path)?,
+        (None, None) => Vec::new(),
+    };
+    if let Some(anlz_path) = arg_value(args, "--anlz-path") {
+        merge_anlz_cues(&mut xml_tracks, parse_anlz_folder(&anlz_path)?);
+    }
+    return Ok(xml_tracks);
+}
+
+// With --playlist NAME, only the tracks of that playlist (by name or folder/name path)
+// keep their lighting data. Also returns the playlist's (ID, title) entries in set order.
+// Playlists are only read from the collection XML and name its track IDs, so they can't
+// select tracks loaded from --usb-path, whose IDs are export.pdb rows.
+fn select_playlist(
+    args: &Vec<String>,
+    mut xml_tracks: Vec<XmlTrackInfo>,
+) -> Result<(Vec<XmlTrackInfo>, Option<Vec<(u32, String)>>), Box<dyn Error>> {
+    let name = match arg_value(args, "--playlist") {
+        Some(name) => name,
+        None => return Ok((xml_tracks, None)),
+    };
+    if arg_value(args, "--usb-path").is_some() {
+        return Err("--playlist can't be used with --usb-path, whose track IDs aren't the XML's".into());
+    }
+    let collection_xml_path = arg_value(args, "--collection-xml-path")
+        .ok_or("--playlist needs --collection-xml-path to read the playlist from")?;
+    let playlist = parse_rekordbox_playlists(&collection_xml_path, &xml_tracks)?
+        .into_iter()
+        .find(|playlist| playlist.is_named(&name))
+        .ok_or(format!("no playlist named '{}' in {}", name, collection_xml_path))?;
+    xml_tracks.retain(|track| playlist.track_ids.contains(&track.id));
+    let entries = playlist
+        .track_ids
+        .iter()
+        .map(|id| {
+            let title = xml_tracks
+                .iter()
+                .find(|track| track.id == *id)
+                .map_or("unknown".to_string(), |track| format!("{} - {}", track.artist, track.title));
+            (*id, title)
+        })
+        .collect();
+    println!("restricted lighting cues to {} tracks of playlist {}", xml_tracks.len(), playlist.path);
+    return Ok((xml_tracks, Some(entries)));
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
-    let rekordbox_access = RekordboxAccess::make(collection_xml_path);
+    match args.get(1).map(String::as_str) {
+        Some("write-cues") => return write_cues(&args),
+        Some("lint") => return lint(&args),
+        Some("bench-xml") => {
+            let track_count = arg_value(&args, "--tracks")
+                .and_then(|tracks| tracks.parse::<usize>().ok())
+                .unwrap_or(60_000);
+            return benchmark_xml_loaders(track_count);
+        }
+        _ => {}
+    }
+    let (xml_tracks, set_playlist) = select_playlist(&args, load_collection(&args)?)?;
+
+    // --boards N drives N PCA9685 boards at I2C addresses 0x40, 0x41, ...; 0 disables output
+    let board_count = arg_value(&args, "--boards")
+        .and_then(|boards| boards.parse::<u8>().ok())
+        .unwrap_or(1);
+    // without --patch-path, every board output is a dimmer named C{board}L{output}
+    let patch = match arg_value(&args, "--patch-path") {
+        Some(patch_path) => Patch::from_json(&patch_path)?,
+        None => Patch::for_boards(board_count.max(1) as usize, OUTPUTS_PER_BOARD),
+    };
+
+    // with a set playlist, only the shows its tracks cue are loaded
+    let shows_path = arg_value(&args, "--shows-path").unwrap_or("shows/shows.json".to_string());
+    let cued_shows: Option<HashSet<String>> = set_playlist.as_ref().map(|_| {
+        xml_tracks
+            .iter()
+            .flat_map(|track| track.cues.iter())
+            .filter_map(|cue| cue.lighting.as_ref())
+            .map(|lighting| lighting.show().to_string())
+            .collect()
+    });
+    let shows_manager = ShowsManager::from_json(&shows_path, cued_shows.as_ref(), &patch)?;
+    let overrides = Overrides::make(&patch);
+
+    let output: Box<dyn LightingOutput> = if board_count == 0 {
+        Box::new(NoOutput)
+    } else {
+        Box::new(Pca9685Output::make((0..board_count).map(|i| 0x40 + i).collect(), patch))
+    };
 
-    let mut tuber = Tuber::create(shows_manager, rekordbox_access, Box::new(mcp))
-        .expect("Could not create tuber");
+    let rekordbox_access = RekordboxAccess::make(xml_tracks);
+    let mut tuber = Tuber::create(
+        shows_manager,
+        rekordbox_access,
+        output,
+        overrides,
+        set_playlist.unwrap_or(Vec::new()),
+    )
+    .expect("Could not create tuber");
     tuber.tick_loop()
 }
@@ -0,0 +1,112 @@
+use pwm_pca9685::Pca9685;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::patch::Patch;
+
+pub trait LightingOutput {
+    // Writes every patched channel of the frame this output drives; returns false if
+    // nothing was written.
+    fn write_frame_mapped(&mut self, frame: &HashMap<String, f64>) -> bool;
+
+    // Why frames aren't reaching the rig, for the TUI to show; None while they are.
+    fn status(&self) -> Option<String> {
+        return None;
+    }
+}
+
+pub const OUTPUTS_PER_BOARD: usize = 16;
+
+// PCA9685 PWM boards on the I2C bus of an MCP2221 USB adapter. Universe i of the
+// patch is board i, and addresses 1 to 16 are its outputs.
+pub struct Pca9685Output {
+    board_addresses: Vec<u8>,
+    patch: Patch,
+    pwm: Option<Pca9685<mcp2221::Handle>>,
+    last_connect_attempt: Option<Instant>,
+    // why the last attempt to connect failed, until one succeeds
+    connect_error: Option<String>,
+}
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// about 1.5kHz, the fastest the PCA9685 runs, so dimmed LEDs don't flicker on camera
+const PWM_PRESCALE: u8 = 3;
+const PWM_MAX: u16 = 4095;
+
+impl Pca9685Output {
+    pub fn make(board_addresses: Vec<u8>, patch: Patch) -> Pca9685Output {
+        return Pca9685Output {
+            board_addresses,
+            patch,
+            pwm: None,
+            last_connect_attempt: None,
+            connect_error: None,
+        };
+    }
+
+    fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let handle = mcp2221::Handle::open_first(&mcp2221::Config::default())?;
+        let mut pwm = Pca9685::new(handle, self.board_addresses[0]).map_err(|e| format!("{:?}", e))?;
+        for address in self.board_addresses.iter() {
+            pwm.set_address(*address).map_err(|e| format!("{:?}", e))?;
+            pwm.set_prescale(PWM_PRESCALE).map_err(|e| format!("{:?}", e))?;
+            pwm.enable().map_err(|e| format!("{:?}", e))?;
+        }
+        self.pwm = Some(pwm);
+        return Ok(());
+    }
+}
+
+impl LightingOutput for Pca9685Output {
+    fn write_frame_mapped(&mut self, frame: &HashMap<String, f64>) -> bool {
+        if self.pwm.is_none() {
+            if self
+                .last_connect_attempt
+                .map_or(false, |attempt| attempt.elapsed() < RECONNECT_INTERVAL)
+            {
+                return false;
+            }
+            self.last_connect_attempt = Some(Instant::now());
+            // retried every RECONNECT_INTERVAL, so the error is shown by the TUI
+            // rather than printed over it
+            if let Err(error) = self.connect() {
+                self.connect_error = Some(error.to_string());
+                return false;
+            }
+            self.connect_error = None;
+        }
+        let mut off = vec![[0; OUTPUTS_PER_BOARD]; self.board_addresses.len()];
+        for (universe, slot, level) in self.patch.map_frame(frame) {
+            if let Some(value) = off.get_mut(universe).and_then(|board| board.get_mut(slot)) {
+                *value = (level.clamp(0., 1.) * PWM_MAX as f64) as u16;
+            }
+        }
+        let pwm = self.pwm.as_mut().unwrap();
+        for (address, off) in self.board_addresses.iter().zip(off.iter()) {
+            let written = pwm.set_address(*address).is_ok()
+                && pwm.set_all_on_off(&[0; OUTPUTS_PER_BOARD], off).is_ok();
+            if !written {
+                self.pwm = None;
+                return false;
+            }
+        }
+        return true;
+    }
+
+    fn status(&self) -> Option<String> {
+        let board_count = self.board_addresses.len();
+        return self
+            .connect_error
+            .as_ref()
+            .map(|error| format!("could not connect to {} PCA9685 boards: {}", board_count, error));
+    }
+}
+
+// Used when no hardware is configured, so the TUI can still preview shows.
+pub struct NoOutput;
+
+impl LightingOutput for NoOutput {
+    fn write_frame_mapped(&mut self, _frame: &HashMap<String, f64>) -> bool {
+        return false;
+    }
+}
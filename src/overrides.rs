@@ -0,0 +1,207 @@
+use crossterm::event::KeyCode;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::patch::Patch;
+
+// Overrides are the operator's hands on the rig, applied to the shows' frame last before
+// it is written out. Keys toggle them in the TUI:
+//   b  blackout        f  full on        s  strobe        z  freeze the current frame
+//   -  master down     =  master up      1-9  mute the patch's groups, in name order
+//   esc  clear every override
+// Blackout wins over everything, muted groups stay dark under full on and strobe, and
+// the master dimmer scales whatever else is output.
+// Only freezing touches moving heads' pan and tilt, which are positions rather than levels.
+
+const MASTER_STEP: f64 = 0.1;
+const STROBE_PERIOD: Duration = Duration::from_millis(100);
+const STROBE_FLASH: Duration = Duration::from_millis(30);
+
+pub struct Overrides {
+    blackout: bool,
+    full_on: bool,
+    // when the strobe was started, so every flash is the same length
+    strobe: Option<Instant>,
+    frozen: Option<HashMap<String, f64>>,
+    master: f64,
+    muted: HashSet<String>,
+    // the patch's groups in name order, with the channels of their fixtures
+    groups: Vec<(String, Vec<String>)>,
+    // the last frame from the shows, to freeze on
+    last_frame: HashMap<String, f64>,
+    // moving heads' pan and tilt channels
+    positions: HashSet<String>,
+}
+
+impl Overrides {
+    pub fn make(patch: &Patch) -> Overrides {
+        let groups = patch
+            .group_names()
+            .into_iter()
+            .map(|group| {
+                let channels = patch
+                    .group_fixtures(&group)
+                    .unwrap_or(Vec::new())
+                    .iter()
+                    .flat_map(|fixture| fixture.frame_channels())
+                    .collect();
+                (group, channels)
+            })
+            .collect();
+        return Overrides {
+            blackout: false,
+            full_on: false,
+            strobe: None,
+            frozen: None,
+            master: 1.,
+            muted: HashSet::new(),
+            groups,
+            last_frame: HashMap::new(),
+            positions: patch.home_levels().into_keys().collect(),
+        };
+    }
+
+    // Kept to whole steps, so stepping down and back up returns to full.
+    fn set_master(&mut self, master: f64) {
+        self.master = ((master / MASTER_STEP).round() * MASTER_STEP).clamp(0., 1.);
+    }
+
+    // Keys that don't control an override are ignored.
+    pub fn handle_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Char('b') => self.blackout = !self.blackout,
+            KeyCode::Char('f') => self.full_on = !self.full_on,
+            KeyCode::Char('s') => {
+                self.strobe = match self.strobe {
+                    Some(_) => None,
+                    None => Some(Instant::now()),
+                }
+            }
+            KeyCode::Char('z') => {
+                self.frozen = match self.frozen {
+                    Some(_) => None,
+                    None => Some(self.last_frame.clone()),
+                }
+            }
+            KeyCode::Char('-') => self.set_master(self.master - MASTER_STEP),
+            KeyCode::Char('=') | KeyCode::Char('+') => self.set_master(self.master + MASTER_STEP),
+            KeyCode::Char(digit @ '1'..='9') => {
+                let index = digit as usize - '1' as usize;
+                let group = match self.groups.get(index) {
+                    Some((group, _)) => group.clone(),
+                    None => return,
+                };
+                if !self.muted.remove(&group) {
+                    self.muted.insert(group);
+                }
+            }
+            KeyCode::Esc => {
+                self.blackout = false;
+                self.full_on = false;
+                self.strobe = None;
+                self.frozen = None;
+                self.master = 1.;
+                self.muted.clear();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn apply(&mut self, frame: HashMap<String, f64>) -> HashMap<String, f64> {
+        self.last_frame = frame;
+        let mut frame = self.frozen.as_ref().unwrap_or(&self.last_frame).clone();
+        let muted: HashSet<&String> = self
+            .groups
+            .iter()
+            .filter(|(group, _)| self.muted.contains(group))
+            .flat_map(|(_, channels)| channels.iter())
+            .collect();
+        let flash = self.strobe.map(|start| {
+            let period = STROBE_PERIOD.as_secs_f64();
+            start.elapsed().as_secs_f64() % period < STROBE_FLASH.as_secs_f64()
+        });
+        for (channel, level) in frame
+            .iter_mut()
+            .filter(|(channel, _)| !self.positions.contains(*channel))
+        {
+            if self.full_on {
+                *level = 1.;
+            }
+            match flash {
+                Some(true) => *level = 1.,
+                Some(false) => *level = 0.,
+                None => {}
+            }
+            if muted.contains(channel) {
+                *level = 0.;
+            }
+            *level *= self.master;
+            if self.blackout {
+                *level = 0.;
+            }
+        }
+        return frame;
+    }
+
+    // The overrides applied to the shows' last frame again, for when the shows have no new
+    // frame (e.g. while Rekordbox is detached); None before their first frame.
+    pub fn reapply(&mut self) -> Option<HashMap<String, f64>> {
+        if self.last_frame.is_empty() {
+            return None;
+        }
+        return Some(self.apply(self.last_frame.clone()));
+    }
+
+    // A reminder of the keys, with the number of each group.
+    pub fn keys(&self) -> String {
+        let groups = self
+            .groups
+            .iter()
+            .take(9)
+            .enumerate()
+            .map(|(i, (group, _))| format!("{} {}", i + 1, group))
+            .collect::<Vec<String>>()
+            .join(", ");
+        return format!(
+            "b blackout | f full | s strobe | z freeze | -/= master | mute: {} | esc clear",
+            if groups.is_empty() {
+                "no groups"
+            } else {
+                &groups
+            }
+        );
+    }
+
+    // The overrides in effect, e.g. "BLACKOUT | master 70%", or None.
+    pub fn status(&self) -> Option<String> {
+        let mut active: Vec<String> = Vec::new();
+        if self.blackout {
+            active.push("BLACKOUT".to_string());
+        }
+        if self.full_on {
+            active.push("full on".to_string());
+        }
+        if self.strobe.is_some() {
+            active.push("strobe".to_string());
+        }
+        if self.frozen.is_some() {
+            active.push("frozen".to_string());
+        }
+        if self.master < 1. {
+            active.push(format!("master {:.0}%", self.master * 100.));
+        }
+        let muted: Vec<&str> = self
+            .groups
+            .iter()
+            .map(|(group, _)| group.as_str())
+            .filter(|group| self.muted.contains(*group))
+            .collect();
+        if !muted.is_empty() {
+            active.push(format!("muted {}", muted.join(", ")));
+        }
+        if active.is_empty() {
+            return None;
+        }
+        return Some(active.join(" | "));
+    }
+}
@@ -0,0 +1,521 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+use crate::color::{ColorLayout, COLOR_CHANNELS};
+
+// The patch lists the rig's fixtures and where each of their channels is wired.
+// Frames name channels "fixture.channel", or just "fixture" for fixtures with a
+// single channel; outputs use the patch to find the universe and address of each.
+//
+// {"fixtures": [{"name": "C0L0", "type": "dimmer", "channels": ["intensity"],
+//                "universe": 0, "address": 1}, ...],
+//  "groups": {"front": ["C0L0", "C0L1"], ...},
+//  "mix": {"front": "htp"}}
+//
+// Fixtures of type "rgb", "rgbw", "rgba" or "hsi" are color fixtures: frames give them
+// red, green and blue channels, which are turned into the fixture's channels on output.
+//
+// Fixtures with "pan" and "tilt" channels are moving heads. Frames give their position
+// as pan and tilt levels from 0 to 1, output at 16 bits when the fixture also has
+// "pan_fine" and "tilt_fine" channels. "positions" names presets of pan and tilt, for
+// the whole patch or per fixture: {"home": {"pan": 0.5, "tilt": 0.3}}.
+//
+// Groups are ordered lists of fixture names; "all" is every fixture in patch order.
+// "mix" sets how the decks' shows are mixed on a group's channels (default "sum").
+//
+// Levels are processed on their way out so one show suits different lights: "master"
+// (0 to 1) dims the whole rig, then each fixture's "curve" and "gamma" shape its levels,
+// "min" and "max" clamp them and the channels listed in "invert" are flipped (1 - level).
+// Pan and tilt are only inverted.
+
+pub const POSITION_CHANNELS: [&str; 2] = ["pan", "tilt"];
+const FINE_CHANNELS: [&str; 2] = ["pan_fine", "tilt_fine"];
+// Where heads point when no preset named "home" is given.
+const DEFAULT_HOME: Position = Position { pan: 0.5, tilt: 0.5 };
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct Position {
+    pub pan: f64,
+    pub tilt: f64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Fixture {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub channels: Vec<String>,
+    pub universe: usize,
+    // 1-based, like a DMX start address; the fixture's channels follow it in order
+    pub address: usize,
+    #[serde(default)]
+    pub curve: Curve,
+    #[serde(default = "default_one")]
+    pub gamma: f64,
+    #[serde(default)]
+    pub min: f64,
+    #[serde(default = "default_one")]
+    pub max: f64,
+    // channels output as 1 - level
+    #[serde(default)]
+    pub invert: Vec<String>,
+    // presets for this fixture, taking precedence over the patch's
+    #[serde(default)]
+    pub positions: HashMap<String, Position>,
+}
+
+fn default_one() -> f64 {
+    return 1.;
+}
+
+// How a fixture's levels map to its output, before gamma.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Curve {
+    #[default]
+    Linear,
+    // level squared, the usual law for incandescent dimmers
+    Square,
+    // eased in and out, for dimmers that jump at either end
+    SCurve,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MixMode {
+    // highest takes precedence: the loudest deck's level wins
+    Htp,
+    // levels weighted by each deck's faders and added
+    Sum,
+    // levels weighted by the crossfader position only, ignoring channel faders
+    Crossfade,
+}
+
+#[derive(Deserialize)]
+struct PatchJson {
+    fixtures: Vec<Fixture>,
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    mix: HashMap<String, MixMode>,
+    #[serde(default = "default_one")]
+    master: f64,
+    #[serde(default)]
+    positions: HashMap<String, Position>,
+}
+
+// Where a channel is output and how its levels are processed on the way.
+#[derive(Clone)]
+struct ChannelOutput {
+    universe: usize,
+    // 0-based
+    slot: usize,
+    curve: Curve,
+    gamma: f64,
+    min: f64,
+    max: f64,
+    invert: bool,
+    // pan and tilt: not dimmed or shaped, and split over a fine slot when there is one
+    position: bool,
+    fine_slot: Option<usize>,
+}
+
+// A color fixture's channels, driven together from its color in the frame.
+#[derive(Clone)]
+struct ColorOutput {
+    layout: ColorLayout,
+    // the fixture's red, green and blue frame channels
+    frame_channels: [String; 3],
+    // the output of each of the layout's channels that the fixture has, by index in
+    // layout.channels()
+    outputs: Vec<(usize, ChannelOutput)>,
+}
+
+#[derive(Clone)]
+pub struct Patch {
+    pub fixtures: Vec<Fixture>,
+    groups: HashMap<String, Vec<String>>,
+    mix: HashMap<String, MixMode>,
+    master: f64,
+    positions: HashMap<String, Position>,
+    // frame channel name -> where and how it is output
+    slots: HashMap<String, ChannelOutput>,
+    colors: Vec<ColorOutput>,
+}
+
+impl ChannelOutput {
+    // A frame level (0 to 1), already dimmed by the master, as it should be output.
+    fn process(&self, level: f64) -> f64 {
+        let level = level.clamp(0., 1.);
+        if self.position {
+            return if self.invert { 1. - level } else { level };
+        }
+        let curved = match self.curve {
+            Curve::Linear => level,
+            Curve::Square => level * level,
+            Curve::SCurve => level * level * (3. - 2. * level),
+        };
+        let level = curved.powf(self.gamma).clamp(self.min, self.max);
+        if self.invert {
+            return 1. - level;
+        }
+        return level;
+    }
+
+    // (universe, slot, level) of the channel, and of its fine slot: the level at 16 bits
+    // split into its high and low bytes.
+    fn levels(&self, level: f64) -> Vec<(usize, usize, f64)> {
+        let level = self.process(level);
+        let fine_slot = match self.fine_slot {
+            Some(fine_slot) => fine_slot,
+            None => return vec![(self.universe, self.slot, level)],
+        };
+        let value = (level * 65535.).round() as u32;
+        return vec![
+            (self.universe, self.slot, (value >> 8) as f64 / 255.),
+            (self.universe, fine_slot, (value & 0xFF) as f64 / 255.),
+        ];
+    }
+}
+
+impl Fixture {
+    pub fn channel_name(&self, channel: &str) -> String {
+        if self.channels.len() == 1 && self.color_layout().is_none() {
+            return self.name.clone();
+        }
+        return format!("{}.{}", self.name, channel);
+    }
+
+    // Settings that can't be applied as given fail the patch load rather than being
+    // guessed at or skipped.
+    fn check(&self) -> Result<(), String> {
+        if self.address == 0 {
+            return Err(format!("fixture {} has address 0, addresses start at 1", self.name));
+        }
+        if self.gamma <= 0. {
+            return Err(format!("fixture {} has gamma {}, it must be more than 0", self.name, self.gamma));
+        }
+        if !(0. ..=1.).contains(&self.min) || !(0. ..=1.).contains(&self.max) || self.min > self.max {
+            return Err(format!(
+                "fixture {} has min {} and max {}, they must be from 0 to 1 with min at most max",
+                self.name, self.min, self.max
+            ));
+        }
+        return Ok(());
+    }
+
+    pub fn color_layout(&self) -> Option<ColorLayout> {
+        return ColorLayout::from_kind(&self.kind);
+    }
+
+    pub fn is_moving_head(&self) -> bool {
+        return POSITION_CHANNELS.iter().all(|channel| self.channels.iter().any(|c| c == channel));
+    }
+
+    // The fixture's channels as frames name them: a color fixture has red, green and blue
+    // instead of its layout's channels, followed by its other channels. Fine channels are
+    // left out, as they are output from pan and tilt.
+    pub fn frame_channels(&self) -> Vec<String> {
+        let others = self
+            .channels
+            .iter()
+            .filter(|channel| !FINE_CHANNELS.contains(&channel.as_str()));
+        let layout = match self.color_layout() {
+            Some(layout) => layout,
+            None => return others.map(|channel| self.channel_name(channel)).collect(),
+        };
+        return COLOR_CHANNELS
+            .iter()
+            .map(|channel| self.channel_name(channel))
+            .chain(
+                others
+                    .filter(|channel| !layout.channels().contains(&channel.as_str()))
+                    .map(|channel| self.channel_name(channel)),
+            )
+            .collect();
+    }
+
+    // frame_channels() without a moving head's pan and tilt, which are positions rather
+    // than levels.
+    pub fn level_channels(&self) -> Vec<String> {
+        let positions: Vec<String> = POSITION_CHANNELS
+            .iter()
+            .filter(|_| self.is_moving_head())
+            .map(|channel| self.channel_name(channel))
+            .collect();
+        return self
+            .frame_channels()
+            .into_iter()
+            .filter(|channel| !positions.contains(channel))
+            .collect();
+    }
+}
+
+impl Patch {
+    fn make(
+        fixtures: Vec<Fixture>,
+        groups: HashMap<String, Vec<String>>,
+        mix: HashMap<String, MixMode>,
+        master: f64,
+        positions: HashMap<String, Position>,
+    ) -> Patch {
+        let mut slots = HashMap::new();
+        let mut colors = Vec::new();
+        for fixture in fixtures.iter() {
+            for channel in fixture.invert.iter().filter(|channel| !fixture.channels.contains(channel)) {
+                println!("fixture {} inverts unknown channel {}", fixture.name, channel);
+            }
+            let layout = fixture.color_layout();
+            let mut color_outputs = Vec::new();
+            for (i, channel) in fixture.channels.iter().enumerate() {
+                if FINE_CHANNELS.contains(&channel.as_str()) {
+                    continue;
+                }
+                let position = fixture.is_moving_head() && POSITION_CHANNELS.contains(&channel.as_str());
+                let fine_slot = fixture
+                    .channels
+                    .iter()
+                    .position(|c| position && *c == format!("{}_fine", channel))
+                    .map(|fine| fixture.address + fine - 1);
+                let output = ChannelOutput {
+                    universe: fixture.universe,
+                    slot: fixture.address + i - 1,
+                    curve: fixture.curve,
+                    gamma: fixture.gamma,
+                    min: fixture.min,
+                    max: fixture.max,
+                    invert: fixture.invert.contains(channel),
+                    position,
+                    fine_slot,
+                };
+                if let Some(index) = layout.and_then(|layout| layout.channels().iter().position(|c| c == channel)) {
+                    color_outputs.push((index, output));
+                } else if slots.insert(fixture.channel_name(channel), output).is_some() {
+                    println!("channel {} is patched more than once", fixture.channel_name(channel));
+                }
+            }
+            if let Some(layout) = layout {
+                for channel in layout.channels().iter().filter(|c| !fixture.channels.iter().any(|f| f == *c)) {
+                    println!("{} fixture {} has no {} channel", fixture.kind, fixture.name, channel);
+                }
+                colors.push(ColorOutput {
+                    layout,
+                    frame_channels: COLOR_CHANNELS.map(|channel| fixture.channel_name(channel)),
+                    outputs: color_outputs,
+                });
+            }
+        }
+        for (group, members) in groups.iter() {
+            for member in members.iter().filter(|member| !fixtures.iter().any(|f| &f.name == *member)) {
+                println!("group {} names unknown fixture {}", group, member);
+            }
+        }
+        let patch = Patch {
+            fixtures,
+            groups,
+            mix,
+            master,
+            positions,
+            slots,
+            colors,
+        };
+        for group in patch.mix.keys().filter(|group| patch.group_fixtures(group).is_none()) {
+            println!("mix mode set for unknown group {}", group);
+        }
+        return patch;
+    }
+
+    pub fn from_json(patch_json_path: &str) -> Result<Patch, String> {
+        let json_content = read_to_string(patch_json_path)
+            .map_err(|error| format!("could not read patch {}: {}", patch_json_path, error))?;
+        let json: PatchJson = serde_json::from_str(&json_content)
+            .map_err(|error| format!("patch {} is not valid: {}", patch_json_path, error))?;
+        if !(0. ..=1.).contains(&json.master) {
+            return Err(format!("patch master is {}, it must be from 0 to 1", json.master));
+        }
+        for fixture in json.fixtures.iter() {
+            fixture.check()?;
+        }
+        let patch = Patch::make(json.fixtures, json.groups, json.mix, json.master, json.positions);
+        let mut kinds: Vec<String> = patch.fixtures.iter().map(|fixture| fixture.kind.clone()).collect();
+        kinds.sort();
+        kinds.dedup();
+        println!(
+            "loaded patch with {} fixtures ({}) and {} channels",
+            patch.fixtures.len(),
+            kinds.join(", "),
+            patch.fixtures.iter().map(|fixture| fixture.channels.len()).sum::<usize>()
+        );
+        return Ok(patch);
+    }
+
+    // One single-channel dimmer per output of each board, named C{board}L{output}.
+    pub fn for_boards(board_count: usize, outputs_per_board: usize) -> Patch {
+        let fixtures = (0..board_count)
+            .flat_map(|board| {
+                (0..outputs_per_board).map(move |output| Fixture {
+                    name: format!("C{}L{}", board, output),
+                    kind: "dimmer".to_string(),
+                    channels: vec!["intensity".to_string()],
+                    universe: board,
+                    address: output + 1,
+                    curve: Curve::Linear,
+                    gamma: 1.,
+                    min: 0.,
+                    max: 1.,
+                    invert: Vec::new(),
+                    positions: HashMap::new(),
+                })
+            })
+            .collect();
+        return Patch::make(fixtures, HashMap::new(), HashMap::new(), 1., HashMap::new());
+    }
+
+    // Every frame channel name in fixture order.
+    pub fn channel_names(&self) -> Vec<String> {
+        return self.fixtures.iter().flat_map(|fixture| fixture.frame_channels()).collect();
+    }
+
+    // Every frame channel name in fixture order, without moving heads' pan and tilt.
+    pub fn level_channel_names(&self) -> Vec<String> {
+        return self.fixtures.iter().flat_map(|fixture| fixture.level_channels()).collect();
+    }
+
+    // The fixtures of a group in the group's order, or None for an unknown group.
+    pub fn group_fixtures(&self, group: &str) -> Option<Vec<&Fixture>> {
+        if group == "all" {
+            return Some(self.fixtures.iter().collect());
+        }
+        return Some(
+            self.groups
+                .get(group)?
+                .iter()
+                .filter_map(|name| self.fixtures.iter().find(|fixture| &fixture.name == name))
+                .collect(),
+        );
+    }
+
+    // The names of the patch's groups, sorted, without "all".
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.groups.keys().cloned().collect();
+        names.sort();
+        return names;
+    }
+
+    // A moving head's preset position: its own, or the patch's. "home" always exists.
+    pub fn position(&self, fixture: &Fixture, preset: &str) -> Option<Position> {
+        let position = fixture.positions.get(preset).or(self.positions.get(preset)).copied();
+        if position.is_none() && preset == "home" {
+            return Some(DEFAULT_HOME);
+        }
+        return position;
+    }
+
+    // Every moving head's pan and tilt channels at their home position, where they
+    // stay until a show moves them.
+    pub fn home_levels(&self) -> HashMap<String, f64> {
+        let mut levels = HashMap::new();
+        for fixture in self.fixtures.iter().filter(|fixture| fixture.is_moving_head()) {
+            let home = self.position(fixture, "home").unwrap_or(DEFAULT_HOME);
+            levels.insert(fixture.channel_name("pan"), home.pan);
+            levels.insert(fixture.channel_name("tilt"), home.tilt);
+        }
+        return levels;
+    }
+
+    // The mix mode of every channel; "all" applies first, then the other groups by name.
+    pub fn channel_mix_modes(&self) -> HashMap<String, MixMode> {
+        let mut modes: HashMap<String, MixMode> = self
+            .channel_names()
+            .into_iter()
+            .map(|channel| (channel, MixMode::Sum))
+            .collect();
+        let mut groups: Vec<(&String, &MixMode)> = self.mix.iter().collect();
+        groups.sort_by_key(|(group, _)| (group.as_str() != "all", group.to_string()));
+        for (group, mode) in groups {
+            for fixture in self.group_fixtures(group).unwrap_or(Vec::new()) {
+                for channel in fixture.frame_channels() {
+                    modes.insert(channel, *mode);
+                }
+            }
+        }
+        return modes;
+    }
+
+    // (universe, 0-based slot, output level) for every patched channel in the frame.
+    pub fn map_frame(&self, frame: &HashMap<String, f64>) -> Vec<(usize, usize, f64)> {
+        let mut levels: Vec<(usize, usize, f64)> = Vec::new();
+        for (name, level) in frame.iter() {
+            let output = match self.slots.get(name) {
+                Some(output) => output,
+                None => continue,
+            };
+            // the master dims, it doesn't move heads
+            let level = if output.position { *level } else { level * self.master };
+            levels.extend(output.levels(level));
+        }
+        for color in self.colors.iter() {
+            let rgb = color
+                .frame_channels
+                .each_ref()
+                .map(|channel| frame.get(channel).copied().unwrap_or(0.) * self.master);
+            let layout_levels = color.layout.levels(rgb);
+            for (index, output) in color.outputs.iter() {
+                levels.push((output.universe, output.slot, output.process(layout_levels[*index])));
+            }
+        }
+        return levels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pan(fine_slot: Option<usize>, invert: bool) -> ChannelOutput {
+        return ChannelOutput {
+            universe: 1,
+            slot: 4,
+            curve: Curve::Linear,
+            gamma: 1.,
+            min: 0.,
+            max: 1.,
+            invert,
+            position: true,
+            fine_slot,
+        };
+    }
+
+    #[test]
+    fn coarse_and_fine() {
+        let output = pan(Some(5), false);
+        assert_eq!(output.levels(0.), vec![(1, 4, 0.), (1, 5, 0.)]);
+        assert_eq!(output.levels(0.5), vec![(1, 4, 128. / 255.), (1, 5, 0.)]);
+        assert_eq!(output.levels(0.25), vec![(1, 4, 64. / 255.), (1, 5, 0.)]);
+        assert_eq!(output.levels(0.001), vec![(1, 4, 0.), (1, 5, 66. / 255.)]);
+        assert_eq!(output.levels(1.), vec![(1, 4, 1.), (1, 5, 1.)]);
+        assert_eq!(output.levels(1.5), vec![(1, 4, 1.), (1, 5, 1.)]);
+    }
+
+    #[test]
+    fn inverted_coarse_and_fine() {
+        let output = pan(Some(5), true);
+        assert_eq!(output.levels(0.25), vec![(1, 4, 191. / 255.), (1, 5, 1.)]);
+        assert_eq!(output.levels(1.), vec![(1, 4, 0.), (1, 5, 0.)]);
+    }
+
+    #[test]
+    fn without_a_fine_slot() {
+        assert_eq!(pan(None, false).levels(0.3), vec![(1, 4, 0.3)]);
+        assert_eq!(pan(None, true).levels(0.25), vec![(1, 4, 0.75)]);
+    }
+
+    #[test]
+    fn positions_are_not_shaped() {
+        let output = ChannelOutput { curve: Curve::Square, gamma: 2.2, min: 0.1, max: 0.8, ..pan(Some(5), false) };
+        assert_eq!(output.levels(0.5), vec![(1, 4, 128. / 255.), (1, 5, 0.)]);
+        let dimmer = ChannelOutput { position: false, fine_slot: None, ..output };
+        assert_eq!(dimmer.levels(0.5), vec![(1, 4, 0.25_f64.powf(2.2).max(0.1))]);
+    }
+}
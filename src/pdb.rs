@@ -0,0 +1,167 @@
+use byteorder::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::anlz::parse_anlz_analysis;
+use crate::rekordbox::{normalize_location, XmlTrackInfo};
+
+// Layouts follow the Deep Symmetry crate-digger notes on the DeviceSQL export.pdb
+// written by rekordbox to USB media. Everything is little-endian.
+
+const TABLE_TRACKS: u32 = 0;
+const TABLE_ARTISTS: u32 = 2;
+
+const PAGE_HEAP_OFFSET: usize = 0x28;
+const ROW_GROUP_SIZE: usize = 0x24;
+
+const TRACK_ARTIST_ID: usize = 0x44;
+const TRACK_ID: usize = 0x48;
+const TRACK_DURATION: usize = 0x54;
+const TRACK_STRING_OFFSETS: usize = 0x5E;
+const TRACK_ANALYZE_PATH_STRING: usize = 14;
+const TRACK_TITLE_STRING: usize = 17;
+const TRACK_FILE_PATH_STRING: usize = 20;
+
+struct PdbTrack {
+    id: u32,
+    artist_id: u32,
+    duration: u16,
+    title: String,
+    file_path: String,
+    analyze_path: String,
+}
+
+fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    return bytes.get(offset).copied();
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    return Some(LittleEndian::read_u16(bytes.get(offset..offset + 2)?));
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    return Some(LittleEndian::read_u32(bytes.get(offset..offset + 4)?));
+}
+
+fn read_device_sql_string(bytes: &[u8], offset: usize) -> Option<String> {
+    let length_and_kind = read_u8(bytes, offset)?;
+    if length_and_kind & 1 == 1 {
+        let length = (length_and_kind >> 1) as usize;
+        let text = bytes.get(offset + 1..offset + length)?;
+        return Some(String::from_utf8_lossy(text).to_string());
+    }
+    let length = read_u16(bytes, offset + 1)? as usize;
+    let text = bytes.get(offset + 4..offset + length)?;
+    return match length_and_kind {
+        0x40 => Some(String::from_utf8_lossy(text).to_string()),
+        0x90 => {
+            let units: Vec<u16> = text.chunks_exact(2).map(LittleEndian::read_u16).collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        _ => None,
+    };
+}
+
+// Returns the heap-relative rows of every data page belonging to the given table.
+fn table_rows(bytes: &[u8], table_type: u32) -> Option<Vec<&[u8]>> {
+    let len_page = read_u32(bytes, 0x04)? as usize;
+    let num_tables = read_u32(bytes, 0x08)? as usize;
+    let (first_page, last_page) = (0..num_tables)
+        .map(|i| 0x1C + i * 16)
+        .find(|table| read_u32(bytes, *table) == Some(table_type))
+        .and_then(|table| Some((read_u32(bytes, table + 8)?, read_u32(bytes, table + 12)?)))?;
+
+    let mut rows = Vec::new();
+    let mut page_index = first_page;
+    loop {
+        let page = bytes.get(page_index as usize * len_page..(page_index as usize + 1) * len_page)?;
+        let page_flags = read_u8(page, 0x1B)?;
+        if read_u32(page, 0x08)? == table_type && page_flags & 0x40 == 0 {
+            let num_rows_small = read_u8(page, 0x18)? as usize;
+            let num_rows_large = read_u16(page, 0x22)? as usize;
+            let num_rows = if num_rows_large > num_rows_small && num_rows_large != 0x1FFF {
+                num_rows_large
+            } else {
+                num_rows_small
+            };
+            let num_row_groups = if num_rows == 0 { 0 } else { (num_rows - 1) / 16 + 1 };
+            for group in 0..num_row_groups {
+                let base = len_page - group * ROW_GROUP_SIZE;
+                let present_flags = read_u16(page, base - 4)?;
+                for row in 0..16 {
+                    if (present_flags >> row) & 1 == 0 {
+                        continue;
+                    }
+                    let row_offset = read_u16(page, base - (6 + 2 * row))? as usize;
+                    rows.push(page.get(PAGE_HEAP_OFFSET + row_offset..)?);
+                }
+            }
+        }
+        if page_index == last_page {
+            break;
+        }
+        page_index = read_u32(page, 0x0C)?;
+    }
+    return Some(rows);
+}
+
+fn parse_artist_row(row: &[u8]) -> Option<(u32, String)> {
+    let subtype = read_u16(row, 0x00)?;
+    let name_offset = if subtype == 0x64 {
+        read_u16(row, 0x0A)? as usize
+    } else {
+        read_u8(row, 0x09)? as usize
+    };
+    return Some((read_u32(row, 0x04)?, read_device_sql_string(row, name_offset)?));
+}
+
+fn parse_track_row(row: &[u8]) -> Option<PdbTrack> {
+    let string_at = |index: usize| -> Option<String> {
+        let offset = read_u16(row, TRACK_STRING_OFFSETS + index * 2)? as usize;
+        return read_device_sql_string(row, offset);
+    };
+    return Some(PdbTrack {
+        id: read_u32(row, TRACK_ID)?,
+        artist_id: read_u32(row, TRACK_ARTIST_ID)?,
+        duration: read_u16(row, TRACK_DURATION)?,
+        title: string_at(TRACK_TITLE_STRING)?,
+        file_path: string_at(TRACK_FILE_PATH_STRING)?,
+        analyze_path: string_at(TRACK_ANALYZE_PATH_STRING).unwrap_or_default(),
+    });
+}
+
+// Takes the root of an exported USB stick, i.e. the folder containing PIONEER/.
+pub fn parse_usb_export(usb_path: &String) -> Result<Vec<XmlTrackInfo>, String> {
+    println!("loading rekordbox usb export");
+    let root = Path::new(usb_path);
+    let pdb_path = root.join("PIONEER").join("rekordbox").join("export.pdb");
+    let bytes = std::fs::read(&pdb_path)
+        .map_err(|error| format!("could not read {}: {}", pdb_path.display(), error))?;
+
+    let artists: HashMap<u32, String> = table_rows(&bytes, TABLE_ARTISTS)
+        .ok_or(format!("could not read the artists table of {}", pdb_path.display()))?
+        .into_iter()
+        .filter_map(parse_artist_row)
+        .collect();
+    let xml_tracks: Vec<XmlTrackInfo> = table_rows(&bytes, TABLE_TRACKS)
+        .ok_or(format!("could not read the tracks table of {}", pdb_path.display()))?
+        .into_iter()
+        .filter_map(parse_track_row)
+        .map(|track| XmlTrackInfo {
+            artist: artists.get(&track.artist_id).cloned().unwrap_or_default(),
+            cues: parse_anlz_analysis(&root.join(track.analyze_path.trim_start_matches('/')))
+                .map_or(Vec::new(), |analysis| analysis.cues),
+            title: track.title,
+            id: track.id,
+            location: normalize_location(&track.file_path),
+            duration: Some(track.duration as f64),
+        })
+        .collect();
+    println!(
+        "Finished loading USB export {}, found {} tracks with {} cue points",
+        usb_path,
+        xml_tracks.len(),
+        xml_tracks.iter().map(|track| track.cues.len()).sum::<usize>()
+    );
+    return Ok(xml_tracks);
+}
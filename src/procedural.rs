@@ -0,0 +1,412 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::read_to_string;
+
+use crate::color::parse_hex_color;
+use crate::patch::{Fixture, Patch, Position};
+use crate::shows::MixerState;
+
+// A procedural show is a list of effect layers, each applied to a group of the patch
+// and timed in beats since the show's cue:
+//
+// {"layers": [{"effect": "chase", "group": "front", "period": 4, "width": 1},
+//             {"effect": "strobe", "rate": 0.25, "level": 0.5, "blend": "multiply"}]}
+//
+// Layers are applied in order, each to every channel of the group's fixtures. A layer
+// with a "color" ("#ff8000") sets color fixtures to that color, and other fixtures to
+// its brightness.
+// The mixer effect follows the DJ's mixer rather than the beat, e.g. to dim a layer
+// below it with the faders: {"effect": "mixer", "source": "level", "blend": "multiply"}.
+// The position, circle, figure8 and sweep effects move the group's moving heads round
+// one of the patch's positions ("position", default "home") instead of setting levels:
+// {"effect": "circle", "group": "heads", "position": "crowd", "period": 8, "size": 0.1}
+
+fn default_one() -> f64 {
+    return 1.;
+}
+
+fn default_half() -> f64 {
+    return 0.5;
+}
+
+fn default_group() -> String {
+    return "all".to_string();
+}
+
+fn default_four() -> f64 {
+    return 4.;
+}
+
+fn default_size() -> f64 {
+    return 0.1;
+}
+
+fn default_position() -> String {
+    return "home".to_string();
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(tag = "effect", rename_all = "lowercase")]
+enum Effect {
+    // one lit spot travelling across the group every `period` beats, `width` fixtures wide
+    Chase {
+        #[serde(default = "default_one")]
+        period: f64,
+        #[serde(default = "default_one")]
+        width: f64,
+    },
+    // full on every `every` beats, decaying to off over `decay` beats
+    Pulse {
+        #[serde(default = "default_one")]
+        every: f64,
+        #[serde(default = "default_half")]
+        decay: f64,
+    },
+    // a wave with a `period` in beats, shifted by `spread` periods from the first to the last fixture
+    Sine {
+        #[serde(default = "default_one")]
+        period: f64,
+        #[serde(default)]
+        spread: f64,
+    },
+    // a flash every `rate` beats, on for the `duty` fraction of it
+    Strobe {
+        #[serde(default = "default_one")]
+        rate: f64,
+        #[serde(default = "default_half")]
+        duty: f64,
+    },
+    // from one level to another over `length` beats, then held
+    Fade {
+        #[serde(default)]
+        from: f64,
+        #[serde(default = "default_one")]
+        to: f64,
+        #[serde(default = "default_one")]
+        length: f64,
+    },
+    // a level read from the mixer, scaled so that `depth` 0 stays at full and 1 follows
+    // the mixer all the way down; `deck` (1 or 2) follows one deck instead of the loudest
+    Mixer {
+        #[serde(default)]
+        source: MixerSource,
+        deck: Option<usize>,
+        #[serde(default = "default_one")]
+        depth: f64,
+    },
+    // moving heads held at the layer's position
+    Position,
+    // a circle of radius `size` (in pan and tilt) round the position every `period` beats,
+    // shifted by `spread` periods from the first to the last head
+    Circle {
+        #[serde(default = "default_four")]
+        period: f64,
+        #[serde(default = "default_size")]
+        size: f64,
+        #[serde(default)]
+        spread: f64,
+    },
+    // a figure of eight, `size` either side of the position in pan and half that in tilt
+    #[serde(rename = "figure8")]
+    FigureEight {
+        #[serde(default = "default_four")]
+        period: f64,
+        #[serde(default = "default_size")]
+        size: f64,
+        #[serde(default)]
+        spread: f64,
+    },
+    // panning `size` either side of the position and back
+    Sweep {
+        #[serde(default = "default_four")]
+        period: f64,
+        #[serde(default = "default_size")]
+        size: f64,
+        #[serde(default)]
+        spread: f64,
+    },
+}
+
+impl Effect {
+    // Periods are divided by, so a show with a period of zero or less isn't loaded.
+    fn check(&self) -> Result<(), String> {
+        let (name, beats) = match *self {
+            Effect::Chase { period, .. } | Effect::Sine { period, .. } => ("period", period),
+            Effect::Pulse { every, .. } => ("every", every),
+            Effect::Strobe { rate, .. } => ("rate", rate),
+            Effect::Circle { period, .. }
+            | Effect::FigureEight { period, .. }
+            | Effect::Sweep { period, .. } => ("period", period),
+            _ => return Ok(()),
+        };
+        if beats > 0. {
+            return Ok(());
+        }
+        return Err(format!("{} must be more than 0 beats, not {}", name, beats));
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum MixerSource {
+    // the deck's level through its channel fader and the crossfader
+    #[default]
+    Level,
+    // the crossfader's position, from 0 (left) to 1 (right)
+    Crossfader,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Blend {
+    // highest level wins
+    Max,
+    Add,
+    // scales what the layers below produced
+    Multiply,
+}
+
+fn default_blend() -> Blend {
+    return Blend::Max;
+}
+
+#[derive(Deserialize)]
+struct LayerJson {
+    #[serde(default = "default_group")]
+    group: String,
+    #[serde(default = "default_one")]
+    level: f64,
+    #[serde(default = "default_blend")]
+    blend: Blend,
+    color: Option<String>,
+    #[serde(default = "default_position")]
+    position: String,
+    #[serde(flatten)]
+    effect: Effect,
+}
+
+#[derive(Deserialize)]
+struct ProceduralShowJson {
+    layers: Vec<LayerJson>,
+}
+
+struct Layer {
+    effect: Effect,
+    level: f64,
+    blend: Blend,
+    // the channels of each fixture of the group, in group order, with how much of the
+    // layer's level each gets from its color
+    fixtures: Vec<Vec<(String, f64)>>,
+    // the moving heads of the group, for effects that move them
+    heads: Vec<Head>,
+}
+
+struct Head {
+    pan: String,
+    tilt: String,
+    // the layer's position for this head
+    center: Position,
+}
+
+pub struct ProceduralShow {
+    layers: Vec<Layer>,
+}
+
+fn mixer_level(source: MixerSource, deck: Option<usize>, mixer: &MixerState) -> f64 {
+    if let MixerSource::Crossfader = source {
+        return mixer.crossfader;
+    }
+    return mixer
+        .decks
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| deck.map_or(true, |deck| deck == i + 1))
+        .map(|(_, deck)| deck.gain)
+        .fold(0., f64::max);
+}
+
+// How far a moving effect takes head `index` of `count` from its position, in pan and
+// tilt, at `beats` since the cue; None for effects that set levels.
+fn movement(effect: &Effect, beats: f64, index: usize, count: usize) -> Option<(f64, f64)> {
+    let phase = |period: f64, spread: f64| {
+        2. * PI * (beats / period.max(f64::EPSILON) + spread * index as f64 / count.max(1) as f64)
+    };
+    return match *effect {
+        Effect::Position => Some((0., 0.)),
+        Effect::Circle {
+            period,
+            size,
+            spread,
+        } => {
+            let phase = phase(period, spread);
+            Some((size * phase.cos(), size * phase.sin()))
+        }
+        Effect::FigureEight {
+            period,
+            size,
+            spread,
+        } => {
+            let phase = phase(period, spread);
+            Some((size * phase.sin(), size * (2. * phase).sin() / 2.))
+        }
+        Effect::Sweep {
+            period,
+            size,
+            spread,
+        } => Some((size * phase(period, spread).sin(), 0.)),
+        _ => None,
+    };
+}
+
+// Level of an effect for fixture `index` of `count` at `beats` since the cue.
+fn effect_level(
+    effect: &Effect,
+    beats: f64,
+    index: usize,
+    count: usize,
+    mixer: &MixerState,
+) -> f64 {
+    return match *effect {
+        Effect::Chase { period, width } => {
+            let position = (beats / period).rem_euclid(1.) * count as f64;
+            let distance = (index as f64 - position).rem_euclid(count as f64);
+            let distance = distance.min(count as f64 - distance);
+            (1. - distance / width.max(f64::EPSILON)).max(0.)
+        }
+        Effect::Pulse { every, decay } => {
+            (1. - beats.rem_euclid(every) / decay.max(f64::EPSILON)).max(0.)
+        }
+        Effect::Sine { period, spread } => {
+            let phase = beats / period + spread * index as f64 / count.max(1) as f64;
+            0.5 + 0.5 * (2. * PI * phase).sin()
+        }
+        Effect::Strobe { rate, duty } => {
+            if (beats / rate).rem_euclid(1.) < duty {
+                1.
+            } else {
+                0.
+            }
+        }
+        Effect::Fade { from, to, length } => {
+            from + (to - from) * (beats / length.max(f64::EPSILON)).clamp(0., 1.)
+        }
+        Effect::Mixer {
+            source,
+            deck,
+            depth,
+        } => 1. - depth.clamp(0., 1.) * (1. - mixer_level(source, deck, mixer)),
+        // these move heads rather than set levels
+        Effect::Position
+        | Effect::Circle { .. }
+        | Effect::FigureEight { .. }
+        | Effect::Sweep { .. } => 0.,
+    };
+}
+
+// The fixture's frame channels, each with the part of `color` it shows; all of the level
+// without a color. A moving head's pan and tilt aren't levels, so they are left out.
+fn color_channels(fixture: &Fixture, color: Option<[f64; 3]>) -> Vec<(String, f64)> {
+    let channels = fixture.level_channels();
+    let color = match color {
+        Some(color) => color,
+        None => return channels.into_iter().map(|channel| (channel, 1.)).collect(),
+    };
+    let brightness = color[0].max(color[1]).max(color[2]);
+    let has_color = fixture.color_layout().is_some();
+    return channels
+        .into_iter()
+        .enumerate()
+        .map(|(i, channel)| {
+            // a color fixture's frame channels start with red, green and blue
+            let part = if has_color && i < 3 {
+                color[i]
+            } else {
+                brightness
+            };
+            (channel, part)
+        })
+        .collect();
+}
+
+impl ProceduralShow {
+    pub fn from_json(path: &str, patch: &Patch) -> Result<ProceduralShow, String> {
+        let json_content = read_to_string(path).map_err(|error| error.to_string())?;
+        let json: ProceduralShowJson = serde_json::from_str(&json_content)
+            .map_err(|error| format!("not a valid procedural show: {}", error))?;
+        let mut layers = Vec::new();
+        for layer in json.layers.into_iter() {
+            layer.effect.check()?;
+            let fixtures = patch
+                .group_fixtures(&layer.group)
+                .ok_or(format!("unknown group {}", layer.group))?;
+            let color = match &layer.color {
+                Some(color) => Some(
+                    parse_hex_color(color).ok_or(format!("color {} is not like #rrggbb", color))?,
+                ),
+                None => None,
+            };
+            if movement(&layer.effect, 0., 0, 1).is_some() {
+                let mut heads = Vec::new();
+                for fixture in fixtures.iter().filter(|fixture| fixture.is_moving_head()) {
+                    let center = patch.position(fixture, &layer.position).ok_or(format!(
+                        "{} has no position {}",
+                        fixture.name, layer.position
+                    ))?;
+                    heads.push(Head {
+                        pan: fixture.channel_name("pan"),
+                        tilt: fixture.channel_name("tilt"),
+                        center,
+                    });
+                }
+                layers.push(Layer {
+                    effect: layer.effect,
+                    level: layer.level,
+                    blend: layer.blend,
+                    fixtures: Vec::new(),
+                    heads,
+                });
+                continue;
+            }
+            layers.push(Layer {
+                effect: layer.effect,
+                level: layer.level,
+                blend: layer.blend,
+                fixtures: fixtures
+                    .iter()
+                    .map(|fixture| color_channels(fixture, color))
+                    .collect(),
+                heads: Vec::new(),
+            });
+        }
+        return Ok(ProceduralShow { layers });
+    }
+
+    // Channel levels at `beats` since the cue; channels no layer touches are left out.
+    pub fn get_levels(&self, beats: f64, mixer: &MixerState) -> HashMap<String, f64> {
+        let mut levels: HashMap<String, f64> = HashMap::new();
+        for layer in self.layers.iter() {
+            // a moving layer sets its heads' positions over the layers before it
+            let count = layer.heads.len();
+            for (index, head) in layer.heads.iter().enumerate() {
+                let (pan, tilt) = movement(&layer.effect, beats, index, count).unwrap_or((0., 0.));
+                levels.insert(head.pan.clone(), (head.center.pan + pan).clamp(0., 1.));
+                levels.insert(head.tilt.clone(), (head.center.tilt + tilt).clamp(0., 1.));
+            }
+            let count = layer.fixtures.len();
+            for (index, channels) in layer.fixtures.iter().enumerate() {
+                let level = effect_level(&layer.effect, beats, index, count, mixer) * layer.level;
+                for (channel, part) in channels.iter() {
+                    let level = level * part;
+                    let current = levels.entry(channel.clone()).or_insert(0.);
+                    *current = match layer.blend {
+                        Blend::Max => current.max(level),
+                        Blend::Add => *current + level,
+                        Blend::Multiply => *current * level,
+                    };
+                }
+            }
+        }
+        return levels;
+    }
+}
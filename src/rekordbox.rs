@@ -2,10 +2,14 @@ use byteorder::*;
 use minidom::Element;
 use process_list::for_each_module;
 use read_process_memory::*;
-use std::{convert::TryInto, fmt};
+use std::{convert::TryInto, error::Error, fmt};
 use sysinfo::{PidExt, ProcessExt, SystemExt};
 use roxmltree::Document;
 
+use crate::cue_comment::{parse_cue_comment, LightingCue};
+use crate::shows::GRAPH_CHARS;
+use crate::track_matching::{MatchConfidence, TrackIndex};
+
 const TRACK_1_OFFSET: [u32; 4] = [0x03FB2B08, 0x0, 0x230, 0x148];
 const TRACK_2_OFFSET: [u32; 4] = [0x03FB2B08, 0x8, 0x230, 0x148];
 
@@ -89,6 +93,11 @@ pub struct TrackState {
     pub id: u32,
     pub beat_offset: f64,
     pub last_cue: Option<XmlCueInfo>,
+    // the lighting cue before last_cue, which a transition starts from
+    pub previous_cue: Option<XmlCueInfo>,
+    pub match_confidence: MatchConfidence,
+    // ID of the collection track this deck was matched to
+    pub matched_id: Option<u32>,
 }
 
 fn truncate(s: &str, max_chars: usize) -> String {
@@ -168,13 +177,14 @@ pub struct RekordboxAccess {
     track_2_fader_address: CachedPointerChain,
     crossfader_address: CachedPointerChain,
     xml_tracks: Vec<XmlTrackInfo>,
+    track_index: TrackIndex,
 }
 
 impl RekordboxAccess {
-    pub fn make(collection_xml_path: &String) -> RekordboxAccess {
+    pub fn make(xml_tracks: Vec<XmlTrackInfo>) -> RekordboxAccess {
         let rekordbox_access = RekordboxAccess {
             handle: None,
-            track_1_title_address: CachedPointerChain::make(TRACK_2_TITLE.to_vec()),
+            track_1_title_address: CachedPointerChain::make(TRACK_1_TITLE.to_vec()),
             track_1_artist_address: CachedPointerChain::make(TRACK_1_ARTIST.to_vec()),
             track_1_id_address: CachedPointerChain::make(TRACK_1_ID.to_vec()),
             track_1_offset_address: CachedPointerChain::make(TRACK_1_OFFSET.to_vec()),
@@ -185,7 +195,8 @@ impl RekordboxAccess {
             track_1_fader_address: CachedPointerChain::make(TRACK_1_FADER.to_vec()),
             track_2_fader_address: CachedPointerChain::make(TRACK_2_FADER.to_vec()),
             crossfader_address: CachedPointerChain::make(CROSSFADER.to_vec()),
-            xml_tracks: parse_rekordbox_xml(collection_xml_path).unwrap_or(Vec::new()),
+            track_index: TrackIndex::make(&xml_tracks),
+            xml_tracks,
         };
         return rekordbox_access;
     }
@@ -204,26 +215,37 @@ impl RekordboxAccess {
         return self.handle.is_some();
     }
 
-    fn get_last_cue(&self, track: &TrackState) -> Option<XmlCueInfo> {
-        let xml_track = self.xml_tracks.iter().find(|track_info| {
-            // let foundtrack = track_info.title == track.title && track_info.artist == track.artist;
-            // let foundtrack = track_info.title == track.title;
-            // println!("have track for {}, {}: {}", track.title, track.artist, foundtrack);
-            let foundtrack = track_info.id == track.id;
-            return foundtrack;
-        })?;
-        // println!("trackinfo {:?}", xml_track);
-        return xml_track
+    fn match_track(&self, track: &mut TrackState) {
+        match self.track_index.find(track) {
+            Some((i, confidence)) => {
+                track.match_confidence = confidence;
+                track.matched_id = Some(self.xml_tracks[i].id);
+                let (previous_cue, last_cue) = Self::get_last_cues(&self.xml_tracks[i], track);
+                track.previous_cue = previous_cue;
+                track.last_cue = last_cue;
+            }
+            None => {
+                track.match_confidence = MatchConfidence::Unmatched;
+                track.matched_id = None;
+                track.previous_cue = None;
+                track.last_cue = None;
+            }
+        }
+    }
+
+    // The last two lighting cues before the deck's position, earliest first.
+    fn get_last_cues(
+        xml_track: &XmlTrackInfo,
+        track: &TrackState,
+    ) -> (Option<XmlCueInfo>, Option<XmlCueInfo>) {
+        let mut passed = xml_track
             .cues
             .iter()
-            .filter(|cue| {
-                cue.comment
-                    .as_ref()
-                    .map_or(false, |comment| comment.starts_with("EW"))
-            })
+            .filter(|cue| cue.lighting.is_some())
             .filter(|cue| cue.beat_offset < track.beat_offset)
-            .last()
-            .map(std::clone::Clone::clone);
+            .rev();
+        let last_cue = passed.next().cloned();
+        return (passed.next().cloned(), last_cue);
     }
 
     // fn map_raw_fader(raw_fader: f32) -> f32 {
@@ -241,32 +263,46 @@ impl RekordboxAccess {
 
         // println!("gtrack33");
         let mut track_1 = TrackState {
-            // title: self.track_1_title_address.get_string(&handle, false)?,
-            // artist: self.track_1_artist_address.get_string(&handle, false)?,
-            title: "unknown".to_string(),
-            artist: "ua".to_string(),
+            title: self
+                .track_1_title_address
+                .get_string(&handle, false)
+                .unwrap_or_default(),
+            artist: self
+                .track_1_artist_address
+                .get_string(&handle, false)
+                .unwrap_or_default(),
             id: self.track_1_id_address.get_u32(&handle, false)?,
             beat_offset: self.track_1_offset_address.get_f64(&handle, true)?,
             last_cue: None,
+            previous_cue: None,
+            match_confidence: MatchConfidence::Unmatched,
+            matched_id: None,
         };
 
         // println!("gtrack1");
-        track_1.last_cue = self.get_last_cue(&track_1);
+        self.match_track(&mut track_1);
         // let t1cuestring = track_1.last_cue.as_ref().map_or("no cue".to_string(), |cue| cue.comment.as_ref().unwrap_or(&"no comment".to_string()).to_string());
         // println!("track1cue: {:?}", track_1.last_cue);
 
         let mut track_2 = TrackState {
-            // title: self.track_2_title_address.get_string(&handle, false)?,
-            // artist: self.track_2_artist_address.get_string(&handle, false)?,
-            title: "unknown".to_string(),
-            artist: "ua".to_string(),
+            title: self
+                .track_2_title_address
+                .get_string(&handle, false)
+                .unwrap_or_default(),
+            artist: self
+                .track_2_artist_address
+                .get_string(&handle, false)
+                .unwrap_or_default(),
             id: self.track_2_id_address.get_u32(&handle, false)?,
             beat_offset: self.track_2_offset_address.get_f64(&handle, true)?,
             last_cue: None,
+            previous_cue: None,
+            match_confidence: MatchConfidence::Unmatched,
+            matched_id: None,
         };
         // println!("gtrack2");
 
-        track_2.last_cue = self.get_last_cue(&track_2);
+        self.match_track(&mut track_2);
 
         let track_1_fader = self.track_1_fader_address.get_f32(handle, false)? / 1023.;
         let track_2_fader = self.track_2_fader_address.get_f32(handle, false)? / 1023.;
@@ -372,6 +408,34 @@ impl CachedPointerChain {
 pub struct XmlCueInfo {
     pub beat_offset: f64,
     pub comment: Option<String>,
+    pub kind: CueKind,
+    // 0 for hot cue A, None for memory cues
+    pub hot_cue: Option<u8>,
+    pub loop_end_beat: Option<f64>,
+    pub color: Option<(u8, u8, u8)>,
+    pub lighting: Option<LightingCue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CueKind {
+    Cue,
+    FadeIn,
+    FadeOut,
+    Load,
+    Loop,
+}
+
+impl CueKind {
+    // Matches the POSITION_MARK Type attribute of the collection XML.
+    pub fn from_xml_type(mark_type: &str) -> CueKind {
+        return match mark_type {
+            "1" => CueKind::FadeIn,
+            "2" => CueKind::FadeOut,
+            "3" => CueKind::Load,
+            "4" => CueKind::Loop,
+            _ => CueKind::Cue,
+        };
+    }
 }
 
 #[derive(Debug)]
@@ -379,83 +443,186 @@ pub struct XmlTrackInfo {
     pub title: String,
     pub artist: String,
     pub id: u32,
+    pub location: String,
+    pub duration: Option<f64>,
     pub cues: Vec<XmlCueInfo>,
 }
 
-fn parse_xml_cues(track_elem: roxmltree::Node) -> Vec<XmlCueInfo> {
-    let tempo_points: Vec<(f64, f64)> = track_elem
+// Rekordbox stores locations as percent-encoded file URLs in the XML and as plain
+// paths in ANLZ files, so both are reduced to a lowercase forward-slash path.
+pub fn normalize_location(location: &str) -> String {
+    let path = location
+        .strip_prefix("file://localhost")
+        .or_else(|| location.strip_prefix("file://"))
+        .unwrap_or(location);
+    let bytes = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            path.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded).replace('\\', "/");
+    return decoded.trim_start_matches('/').to_lowercase();
+}
+
+// Malformed lighting cues are reported once at load time and otherwise ignored.
+pub fn parse_lighting_cue(comment: &Option<String>) -> Option<LightingCue> {
+    let comment = comment.as_ref()?;
+    return match parse_cue_comment(comment)? {
+        Ok(lighting) => Some(lighting),
+        Err(error) => {
+            println!("ignoring lighting cue '{}': {}", comment, error);
+            None
+        }
+    };
+}
+
+pub fn seconds_to_beats(seconds: f64, tempo_points: &Vec<(f64, f64)>) -> Option<f64> {
+    let (start_seconds, tempo) = tempo_points.get(0)?;
+    return Some((seconds - start_seconds) * tempo / 60.0);
+}
+
+pub fn beats_to_seconds(beats: f64, tempo_points: &Vec<(f64, f64)>) -> Option<f64> {
+    let (start_seconds, tempo) = tempo_points.get(0)?;
+    return Some(start_seconds + beats * 60.0 / tempo);
+}
+
+pub fn tempo_point_from_attributes<'a>(attribute: impl Fn(&str) -> Option<&'a str>) -> Option<(f64, f64)> {
+    return Some((
+        attribute("Inizio")?.parse::<f64>().ok()?,
+        attribute("Bpm")?.parse::<f64>().ok()?,
+    ));
+}
+
+pub fn parse_xml_tempo_points(track_elem: roxmltree::Node) -> Vec<(f64, f64)> {
+    return track_elem
         .children()
         .filter(|child| child.has_tag_name("TEMPO"))
-        .filter_map(|child| {
-            Some((
-                child.attribute("Inizio")?.parse::<f64>().ok()?,
-                child.attribute("Bpm")?.parse::<f64>().ok()?,
-            ))
-        })
+        .filter_map(|child| tempo_point_from_attributes(|name| child.attribute(name)))
         .collect();
-    if let Some((start_seconds, tempo)) = tempo_points.get(0) {
-        let beats_per_second = tempo / 60.0;
-        let mut cues: Vec<XmlCueInfo> = track_elem
-            .children()
-            .filter(|child| child.has_tag_name("POSITION_MARK"))
-            .filter_map(|child| {
-                return Some(XmlCueInfo {
-                    comment: child
-                        .attribute("Name")
-                        .filter(|name| !name.is_empty())
-                        .map(str::to_string),
-                    beat_offset: (child
-                        .attribute("Start")?
-                        .parse::<f64>()
-                        .ok()
-                        .expect("could not parse cue beat offset")
-                        - start_seconds)
-                        * beats_per_second,
-                });
-            })
-            .collect();
-        cues.sort_by(|a, b| a.beat_offset.partial_cmp(&b.beat_offset).unwrap());
-        return cues;
+}
+
+// Shared by the DOM and streaming loaders; `attribute` looks up the attributes of a
+// POSITION_MARK element. A cue without a readable Start fails the load.
+pub fn xml_cue_from_attributes<'a>(
+    attribute: impl Fn(&str) -> Option<&'a str>,
+    tempo_points: &Vec<(f64, f64)>,
+) -> Result<XmlCueInfo, String> {
+    let color_component = |name: &str| attribute(name).and_then(|value| value.parse::<u8>().ok());
+    let color = match (
+        color_component("Red"),
+        color_component("Green"),
+        color_component("Blue"),
+    ) {
+        (Some(red), Some(green), Some(blue)) => Some((red, green, blue)),
+        _ => None,
+    };
+    let comment = attribute("Name")
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+    let start = attribute("Start").ok_or("cue has no Start")?;
+    let start = start
+        .parse::<f64>()
+        .map_err(|_| format!("cue Start '{}' is not a number", start))?;
+    return Ok(XmlCueInfo {
+        lighting: parse_lighting_cue(&comment),
+        comment,
+        beat_offset: seconds_to_beats(start, tempo_points).ok_or("cue's track has no tempo")?,
+        kind: CueKind::from_xml_type(attribute("Type").unwrap_or("0")),
+        hot_cue: attribute("Num").and_then(|num| num.parse::<u8>().ok()),
+        loop_end_beat: attribute("End")
+            .and_then(|end| end.parse::<f64>().ok())
+            .and_then(|end| seconds_to_beats(end, tempo_points)),
+        color,
+    });
+}
+
+// `attribute` looks up the attributes of a COLLECTION/TRACK element. A track without a
+// TrackID, Name or Artist fails the load.
+pub fn xml_track_from_attributes<'a>(
+    attribute: impl Fn(&str) -> Option<&'a str>,
+    mut cues: Vec<XmlCueInfo>,
+) -> Result<XmlTrackInfo, String> {
+    cues.sort_by(|a, b| a.beat_offset.partial_cmp(&b.beat_offset).unwrap());
+    let id = attribute("TrackID").ok_or("track has no TrackID")?;
+    let id = id
+        .parse::<u32>()
+        .map_err(|_| format!("TrackID '{}' is not a number", id))?;
+    return Ok(XmlTrackInfo {
+        title: attribute("Name")
+            .ok_or(format!("track {} has no Name", id))?
+            .to_string(),
+        artist: attribute("Artist")
+            .ok_or(format!("track {} has no Artist", id))?
+            .to_string(),
+        id,
+        location: normalize_location(attribute("Location").unwrap_or("")),
+        duration: attribute("TotalTime").and_then(|total_time| total_time.parse::<f64>().ok()),
+        cues,
+    });
+}
+
+fn parse_xml_cues(track_elem: roxmltree::Node) -> Result<Vec<XmlCueInfo>, String> {
+    let tempo_points = parse_xml_tempo_points(track_elem);
+    if tempo_points.is_empty() {
+        return Ok(Vec::new());
     }
-    return Vec::new();
+    return track_elem
+        .children()
+        .filter(|child| child.has_tag_name("POSITION_MARK"))
+        .map(|child| xml_cue_from_attributes(|name| child.attribute(name), &tempo_points))
+        .collect();
 }
 
-fn parse_rekordbox_xml(path: &String) -> Option<Vec<XmlTrackInfo>> {
+pub fn parse_rekordbox_xml(path: &String) -> Result<Vec<XmlTrackInfo>, Box<dyn Error>> {
     // let file_contents: String = ;
     println!("loading rekordbox xml");
     let raw_xml = std::fs::read_to_string(path)
-        .expect("failed to read xml");
+        .map_err(|error| format!("could not read {}: {}", path, error))?;
     let doc = Document::parse(&raw_xml)
-        .expect("failed to parse xml");
+        .map_err(|error| format!("could not parse {}: {}", path, error))?;
     // println!("rekordbox root: {:?}", root);
     let root = doc.root();
     let collection = root
         .descendants()
         .find(|n| n.has_tag_name("COLLECTION"))
-        .expect("could not find collection tag");
+        .ok_or(format!("{} has no COLLECTION", path))?;
     let xml_tracks: Vec<XmlTrackInfo> = collection
         .children()
         .filter(|n| n.has_tag_name("TRACK"))
         .map(|track_elem| {
-            // println!("track elem: {:?}", track_elem);
-            return XmlTrackInfo {
-                title: track_elem
-                    .attribute("Name")
-                    .expect("could not parse track title")
-                    .to_string(),
-                artist: track_elem
-                    .attribute("Artist")
-                    .expect("could not parse track artist")
-                    .to_string(),
-                id: track_elem
-                    .attribute("TrackID")
-                    .expect("could not read track ID")
-                    .parse::<u32>()
-                    .expect("could not parse track ID"),
-                cues: parse_xml_cues(track_elem),
-            };
+            xml_track_from_attributes(|name| track_elem.attribute(name), parse_xml_cues(track_elem)?)
         })
-        .collect();
+        .collect::<Result<Vec<XmlTrackInfo>, String>>()
+        .map_err(|error| format!("could not parse {}: {}", path, error))?;
     println!("Finished loading XML {}, found {} tracks with {} cue points", path, xml_tracks.len(), xml_tracks.iter().map(|track| track.cues.len()).sum::<usize>());
-    return Some(xml_tracks);
+    return Ok(xml_tracks);
+}
+
+#[derive(Debug, Clone)]
+pub struct XmlPlaylist {
+    // folder names and the playlist name joined with '/'
+    pub path: String,
+    pub track_ids: Vec<u32>,
+}
+
+impl XmlPlaylist {
+    pub fn is_named(&self, name: &str) -> bool {
+        return self.path == name || self.path.rsplit('/').next() == Some(name);
+    }
 }
@@ -0,0 +1,212 @@
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::color::COLOR_CHANNELS;
+use crate::cue_comment::Transition;
+use crate::patch::Patch;
+use crate::rekordbox::{CueKind, XmlCueInfo};
+
+// A scripted show is a Rhai script run once per frame. It reads
+//   beat, bar, beat_in_bar  position since the cue (bar = 4 beats)
+//   gain                    the weight the show is mixed at
+//   show                    the show's title
+//   deck                    the deck playing it (1 or 2), 0 for the idle show
+//   cue                     the lighting cue that started it, () for the idle show
+//   state                   a map kept from one frame to the next, one per deck
+// and sets channel levels (0 to 1) in the `frame` map, e.g.
+//   for channel in group("front") { frame[channel] = if bar % 2 == 0 { 1.0 } else { 0.0 }; }
+// `channels` lists every patched channel, `group(name)` the channels of a patch group
+// (both without moving heads' pan and tilt, which are set by name) and `rand()` returns a number from 0 to 1. `frame.color(fixture, red, green, blue)`
+// sets a color fixture's color.
+//
+// `cue` is a map of the cue's `beat` in the track, `comment`, `kind` ("cue", "fade_in",
+// "fade_out", "load" or "loop"), `hot_cue` (0 for A, () for memory cues), `color`
+// ([red, green, blue] from 0 to 255, or ()), `loop_end` (in beats, or ()), and its
+// lighting directives: `intensity`, `fade` (in beats, or ()), `transition` ("cut",
+// "crossfade" or "wipe") and `transition_beats`.
+// A deck keeps its state from one of the script's cues to the next, and while a
+// transition blends two cues of the same script both run on that state.
+//
+// Scripts can't load modules, evaluate strings or print, and are stopped when a frame
+// takes longer than FRAME_TIME_LIMIT.
+
+const FRAME_TIME_LIMIT: Duration = Duration::from_millis(2);
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct ScriptedShow {
+    engine: Engine,
+    ast: AST,
+    channels: Array,
+    // deck -> the script's state on that deck
+    states: RefCell<HashMap<usize, Map>>,
+    // shows are loaded on the watcher thread, so this is shared with the engine's
+    // progress check as nanoseconds since `created`
+    created: Instant,
+    frame_start: Arc<AtomicU64>,
+    // the last error reported, so a failing script doesn't report every frame
+    last_error: RefCell<Option<String>>,
+}
+
+fn group_channels(patch: &Patch, group: &str) -> Array {
+    return patch
+        .group_fixtures(group)
+        .unwrap_or(Vec::new())
+        .iter()
+        .flat_map(|fixture| fixture.level_channels())
+        .map(Dynamic::from)
+        .collect();
+}
+
+fn make_engine(patch: &Patch, created: Instant, frame_start: Arc<AtomicU64>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.on_print(|_| {});
+    engine.on_debug(|_, _, _| {});
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+    engine.on_progress(move |_| {
+        let frame_time = created.elapsed().as_nanos() as u64 - frame_start.load(Ordering::Relaxed);
+        if frame_time > FRAME_TIME_LIMIT.as_nanos() as u64 {
+            return Some(Dynamic::from("frame time limit exceeded"));
+        }
+        return None;
+    });
+
+    let group_patch = patch.clone();
+    engine.register_fn("group", move |group: &str| group_channels(&group_patch, group));
+    engine.register_fn("color", |frame: &mut Map, fixture: &str, red: f64, green: f64, blue: f64| {
+        for (channel, level) in COLOR_CHANNELS.iter().zip([red, green, blue]) {
+            frame.insert(format!("{}.{}", fixture, channel).into(), Dynamic::from(level));
+        }
+    });
+    // xorshift, seeded from the clock; shows don't need better randomness
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since_epoch| since_epoch.as_nanos() as u64 | 1);
+    let random_state = AtomicU64::new(seed);
+    engine.register_fn("rand", move || {
+        let mut x = random_state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        random_state.store(x, Ordering::Relaxed);
+        return (x >> 11) as f64 / (1u64 << 53) as f64;
+    });
+    return engine;
+}
+
+fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+    return value.map_or(Dynamic::UNIT, Into::into);
+}
+
+// The cue as the script's `cue` map.
+fn cue_map(cue: &XmlCueInfo) -> Map {
+    let kind = match cue.kind {
+        CueKind::Cue => "cue",
+        CueKind::FadeIn => "fade_in",
+        CueKind::FadeOut => "fade_out",
+        CueKind::Load => "load",
+        CueKind::Loop => "loop",
+    };
+    let color = cue.color.map(|(red, green, blue)| {
+        [red, green, blue]
+            .iter()
+            .map(|level| Dynamic::from(*level as i64))
+            .collect::<Array>()
+    });
+    let mut map = Map::new();
+    map.insert("beat".into(), cue.beat_offset.into());
+    map.insert("comment".into(), cue.comment.clone().unwrap_or_default().into());
+    map.insert("kind".into(), kind.into());
+    map.insert("hot_cue".into(), optional(cue.hot_cue.map(|slot| slot as i64)));
+    map.insert("color".into(), optional(color));
+    map.insert("loop_end".into(), optional(cue.loop_end_beat));
+    if let Some(lighting) = &cue.lighting {
+        let (transition, transition_beats) = match lighting.transition() {
+            Transition::Cut => ("cut", 0.),
+            Transition::Crossfade(beats) => ("crossfade", beats),
+            Transition::Wipe(beats) => ("wipe", beats),
+        };
+        map.insert("intensity".into(), lighting.intensity().into());
+        map.insert("fade".into(), optional(lighting.fade_beats()));
+        map.insert("transition".into(), transition.into());
+        map.insert("transition_beats".into(), transition_beats.into());
+    }
+    return map;
+}
+
+impl ScriptedShow {
+    pub fn from_file(path: &str, patch: &Patch) -> Result<ScriptedShow, String> {
+        let script = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let created = Instant::now();
+        let frame_start = Arc::new(AtomicU64::new(0));
+        let engine = make_engine(patch, created, frame_start.clone());
+        let ast = engine
+            .compile(&script)
+            .map_err(|error| format!("could not compile script: {}", error))?;
+        return Ok(ScriptedShow {
+            engine,
+            ast,
+            channels: patch.level_channel_names().into_iter().map(Dynamic::from).collect(),
+            states: RefCell::new(HashMap::new()),
+            created,
+            frame_start,
+            last_error: RefCell::new(None),
+        });
+    }
+
+    // Runs the script for one frame; a failing script leaves the frame empty.
+    pub fn get_levels(
+        &self,
+        title: &str,
+        beats: f64,
+        gain: f64,
+        deck: usize,
+        cue: Option<&XmlCueInfo>,
+    ) -> HashMap<String, f64> {
+        let state = self.states.borrow_mut().remove(&deck).unwrap_or_default();
+        let mut scope = Scope::new();
+        scope.push("beat", beats);
+        scope.push("bar", (beats / 4.).floor() as i64);
+        scope.push("beat_in_bar", beats.rem_euclid(4.));
+        scope.push("gain", gain);
+        scope.push("show", title.to_string());
+        scope.push("deck", deck as i64);
+        scope.push("cue", cue.map_or(Dynamic::UNIT, |cue| Dynamic::from(cue_map(cue))));
+        scope.push("channels", self.channels.clone());
+        scope.push("state", state);
+        scope.push("frame", Map::new());
+
+        self.frame_start.store(self.created.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        if let Some(state) = scope.get_value::<Map>("state") {
+            self.states.borrow_mut().insert(deck, state);
+        }
+        if let Err(error) = result {
+            let error = error.to_string();
+            if self.last_error.borrow().as_ref() != Some(&error) {
+                println!("show script {} failed: {}", title, error);
+                self.last_error.replace(Some(error));
+            }
+            return HashMap::new();
+        }
+        return scope
+            .get_value::<Map>("frame")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(channel, level)| {
+                let level = level.as_float().ok().or(level.as_int().ok().map(|level| level as f64))?;
+                Some((channel.to_string(), level))
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,770 @@
+use itertools::Itertools;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, read_to_string, File};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cue_comment::Transition;
+use crate::patch::{MixMode, Patch};
+use crate::procedural::ProceduralShow;
+use crate::rekordbox::XmlCueInfo;
+use crate::scripted::ScriptedShow;
+
+pub const GRAPH_CHARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// What a show does once it has played for its length.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Playback {
+    #[default]
+    Loop,
+    // stay on the last frame
+    Hold,
+    // go dark
+    Blackout,
+    // play backwards to the start, then forwards again
+    PingPong,
+}
+
+// How often the manifest and the show files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+// A deck moving further than this between two frames has jumped rather than played.
+const IDLE_MAX_STEP: f64 = 1.;
+// A deck whose beat offset hasn't changed for this long has stopped.
+const IDLE_STOPPED_AFTER: Duration = Duration::from_millis(250);
+
+// Tempo that legacy frameRate shows are assumed to have been made at.
+const LEGACY_BPM: f64 = 120.;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShowJson {
+    title: String,
+    path: String,
+    // image shows are timed by one of these, checked in this order
+    frames_per_beat: Option<f64>,
+    frames_per_bar: Option<f64>,
+    // legacy, in frames per second at `bpm` (LEGACY_BPM if not given)
+    frame_rate: Option<f64>,
+    bpm: Option<f64>,
+    #[serde(default)]
+    playback: Playback,
+    // in beats; image shows default to their number of frames over frames per beat,
+    // while procedural and scripted shows without one play on indefinitely
+    length: Option<f64>,
+}
+
+impl ShowJson {
+    fn frames_per_beat(&self) -> Option<f64> {
+        let legacy = self
+            .frame_rate
+            .map(|frame_rate| frame_rate * 60. / self.bpm.unwrap_or(LEGACY_BPM));
+        return self
+            .frames_per_beat
+            .or(self
+                .frames_per_bar
+                .map(|frames_per_bar| frames_per_bar / 4.))
+            .or(legacy)
+            .filter(|frames_per_beat| *frames_per_beat > 0.);
+    }
+}
+
+fn default_idle_fade() -> f64 {
+    return 4.;
+}
+
+fn default_idle_bpm() -> f64 {
+    return LEGACY_BPM;
+}
+
+// The show played while no deck has an active lighting cue.
+#[derive(Deserialize, Clone)]
+struct IdleJson {
+    show: String,
+    // beats to fade in when the last cue ends, and out when a cue starts
+    #[serde(default = "default_idle_fade")]
+    fade: f64,
+    // tempo to keep time at while no deck is playing
+    #[serde(default = "default_idle_bpm")]
+    bpm: f64,
+}
+
+#[derive(Deserialize)]
+struct ShowsJson {
+    shows: Vec<ShowJson>,
+    idle: Option<IdleJson>,
+}
+
+// Where the idle show is and how far it has faded in.
+struct IdleState {
+    beats: f64,
+    level: f64,
+    last_update: Instant,
+    // each deck's beat offset at the last update, and when it last moved
+    deck_offsets: Vec<(f64, Instant)>,
+}
+
+// Rows of the image are the patch's channels in order, columns are frames. Moving heads'
+// pan and tilt have no rows, so a show's pixels never move a head.
+struct ImageShow {
+    frames: Vec<Vec<u8>>,
+    channels: Vec<String>,
+    frames_per_beat: f64,
+}
+
+// A show's path decides its kind: .png files are image shows, .json files procedural
+// ones and .rhai files scripts.
+enum Show {
+    Image(ImageShow),
+    Procedural(ProceduralShow),
+    Scripted(ScriptedShow),
+}
+
+struct LoadedShow {
+    show: Show,
+    playback: Playback,
+    length: Option<f64>,
+}
+
+pub struct ShowsManager {
+    shows: HashMap<String, LoadedShow>,
+    // every patched channel, so a frame always covers the whole rig
+    channels: Vec<String>,
+    mix_modes: HashMap<String, MixMode>,
+    // where each channel's fixture sits in the patch, from 0 (first) to 1, for wipes
+    wipe_positions: HashMap<String, f64>,
+    // moving heads' pan and tilt channels at their home position; these are positions
+    // rather than levels, so they aren't weighted or added up
+    home: HashMap<String, f64>,
+    shows_json_path: String,
+    // shows reloaded by the ShowsWatcher when their files change
+    reloads: Receiver<Reload>,
+    // title (or the manifest's path) -> why it failed to load
+    errors: HashMap<String, String>,
+    idle: Option<IdleJson>,
+    idle_state: IdleState,
+}
+
+// What the ShowsWatcher found changed, loaded and ready to swap in.
+enum Reload {
+    // the manifest couldn't be read, so the shows are left as they are
+    ManifestError(String),
+    Shows {
+        idle: Option<IdleJson>,
+        // when the manifest changed, the titles it still lists; the other shows stop playing
+        titles: Option<HashSet<String>>,
+        // title -> the show, or why it failed to load
+        loaded: Vec<(String, Result<LoadedShow, String>)>,
+    },
+}
+
+// Checks the manifest and the files of its shows for changes on a thread of its own and
+// loads the changed shows there, so frames aren't held up by reading and parsing files.
+struct ShowsWatcher {
+    shows_json_path: String,
+    wanted_titles: Option<HashSet<String>>,
+    patch: Patch,
+    // the image show rows
+    image_channels: Vec<String>,
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+// The show of the lighting cue before a deck's current one.
+pub struct PreviousShow {
+    pub title: String,
+    pub cue: XmlCueInfo,
+    pub beats: f64,
+    pub cue_weight: f64,
+}
+
+// Where a deck is playing and how it is mixed, whether or not it has a lighting cue, for
+// the idle show's time and for shows that follow the mixer.
+pub struct DeckMixer {
+    pub beat_offset: f64,
+    // the deck's level in the mix from its channel fader and the crossfader
+    pub gain: f64,
+}
+
+// The mixer as the DJ has it set.
+pub struct MixerState {
+    pub decks: Vec<DeckMixer>,
+    // from 0 (left) to 1 (right)
+    pub crossfader: f64,
+}
+
+// One deck's active show and where the deck sits in the mix.
+pub struct DeckShow {
+    pub title: String,
+    // the deck's number, from 1
+    pub deck: usize,
+    // the lighting cue that started the show
+    pub cue: XmlCueInfo,
+    // how far the deck has played past the show's cue
+    pub beats: f64,
+    // from the cue's intensity and fade
+    pub cue_weight: f64,
+    // channel fader, 0 to 1
+    pub fader: f64,
+    // how far the crossfader is towards this deck's side, 0 to 1 (0.5 in the middle)
+    pub crossfader: f64,
+    // how the show takes over from the previous one, counted from the current cue
+    pub transition: Transition,
+    pub previous: Option<PreviousShow>,
+}
+
+impl DeckShow {
+    // The deck's level in the mix: its channel fader and a crossfader that passes both
+    // decks at full level in the middle and cuts the far deck at the ends.
+    pub fn gain(&self) -> f64 {
+        return self.cue_weight * self.fader * (2. * self.crossfader).min(1.);
+    }
+
+    // Weight of the deck's levels, which already include the cue weight, in the mix.
+    fn mix_weight(&self, mode: MixMode) -> f64 {
+        return match mode {
+            MixMode::Htp | MixMode::Sum => self.fader * (2. * self.crossfader).min(1.),
+            MixMode::Crossfade => self.crossfader,
+        };
+    }
+}
+
+fn transpose<T>(v: &Vec<Vec<T>>) -> Vec<Vec<T>>
+where
+    T: Clone,
+{
+    assert!(!v.is_empty());
+    (0..v[0].len())
+        .map(|i| v.iter().map(|inner| inner[i].clone()).collect::<Vec<T>>())
+        .collect()
+}
+
+// Paths are compared without a leading "./", as the library scan and the manifest may
+// name the same file either way.
+fn normalize_path(path: &Path) -> PathBuf {
+    return path.strip_prefix(".").unwrap_or(path).to_path_buf();
+}
+
+// The idle show is loaded even when it isn't one of the wanted titles.
+fn is_wanted(
+    wanted_titles: Option<&HashSet<String>>,
+    idle: Option<&IdleJson>,
+    title: &String,
+) -> bool {
+    return wanted_titles.map_or(true, |titles| titles.contains(title))
+        || idle.map_or(false, |idle| &idle.show == title);
+}
+
+fn read_manifest(shows_json_path: &str) -> Result<ShowsJson, String> {
+    let json_content = read_to_string(shows_json_path).map_err(|error| error.to_string())?;
+    return serde_json::from_str(&json_content).map_err(|error| error.to_string());
+}
+
+impl ShowsManager {
+    fn load_image_show(
+        path: &str,
+        frames_per_beat: f64,
+        channels: &Vec<String>,
+    ) -> Result<ImageShow, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|error| error.to_string())?;
+        // the first sample of each pixel (red, or grey) is the light's level
+        let rows: Vec<Vec<u8>> = buf
+            .chunks(info.line_size)
+            .take(info.height as usize)
+            .map(|v| v.iter().copied().step_by(info.color_type.samples()).collect())
+            .collect();
+        if rows.is_empty() || rows[0].is_empty() {
+            return Err("image is empty".to_string());
+        }
+        if rows.len() > channels.len() {
+            println!("{} has {} rows but only {} channels are patched", path, rows.len(), channels.len());
+        }
+        return Ok(ImageShow {
+            frames: transpose(&rows),
+            channels: channels.iter().take(rows.len()).cloned().collect(),
+            frames_per_beat,
+        });
+    }
+
+    fn load_show_kind(
+        show: &ShowJson,
+        patch: &Patch,
+        channels: &Vec<String>,
+    ) -> Result<Show, String> {
+        if show.path.ends_with(".json") {
+            return Ok(Show::Procedural(ProceduralShow::from_json(&show.path, patch)?));
+        }
+        if show.path.ends_with(".rhai") {
+            return Ok(Show::Scripted(ScriptedShow::from_file(&show.path, patch)?));
+        }
+        let frames_per_beat = show
+            .frames_per_beat()
+            .ok_or("image show has no framesPerBeat".to_string())?;
+        if show.frames_per_beat.is_none() && show.frames_per_bar.is_none() {
+            println!(
+                "image show {} uses frameRate, played at {:.2} frames per beat",
+                show.title, frames_per_beat
+            );
+        }
+        return Ok(Show::Image(ShowsManager::load_image_show(&show.path, frames_per_beat, channels)?));
+    }
+
+    fn load_show(
+        show: &ShowJson,
+        patch: &Patch,
+        channels: &Vec<String>,
+    ) -> Result<LoadedShow, String> {
+        let kind = ShowsManager::load_show_kind(show, patch, channels)?;
+        let length = match &kind {
+            Show::Image(image) => show
+                .length
+                .or(Some(image.frames.len() as f64 / image.frames_per_beat)),
+            Show::Procedural(_) | Show::Scripted(_) => show.length,
+        }
+        .filter(|length| *length > 0.);
+        return Ok(LoadedShow {
+            show: kind,
+            playback: show.playback,
+            length,
+        });
+    }
+
+    // With wanted_titles, only those shows are loaded (e.g. the shows cued in a set playlist).
+    // A manifest that can't be read is an error; shows that fail to load are listed in errors().
+    pub fn from_json(
+        shows_json_path: &str,
+        wanted_titles: Option<&HashSet<String>>,
+        patch: &Patch,
+    ) -> Result<ShowsManager, String> {
+        let image_channels = patch.level_channel_names();
+        let json = read_manifest(shows_json_path)
+            .map_err(|error| format!("could not read shows manifest {}: {}", shows_json_path, error))?;
+        let mut shows: HashMap<String, LoadedShow> = HashMap::new();
+        let mut errors: HashMap<String, String> = HashMap::new();
+        let idle = json.idle;
+        for s in json
+            .shows
+            .into_iter()
+            .filter(|s| is_wanted(wanted_titles, idle.as_ref(), &s.title))
+        {
+            match ShowsManager::load_show(&s, patch, &image_channels) {
+                Ok(show) => {
+                    shows.insert(s.title, show);
+                }
+                Err(error) => {
+                    println!("could not load show {} from {}: {}", s.title, s.path, error);
+                    errors.insert(s.title, format!("{}: {}", s.path, error));
+                }
+            }
+        }
+        println!("loaded {} shows", shows.len());
+        let fixture_count = patch.fixtures.len().max(1) as f64;
+        let wipe_positions = patch
+            .fixtures
+            .iter()
+            .enumerate()
+            .flat_map(|(i, fixture)| {
+                fixture
+                    .frame_channels()
+                    .into_iter()
+                    .map(move |channel| (channel, i as f64 / fixture_count))
+            })
+            .collect();
+        let mut watcher = ShowsWatcher {
+            shows_json_path: shows_json_path.to_string(),
+            wanted_titles: wanted_titles.cloned(),
+            patch: patch.clone(),
+            image_channels,
+            modified: HashMap::new(),
+        };
+        watcher.modified = watcher.scan_modified();
+        let (sender, reloads) = channel();
+        thread::spawn(move || watcher.run(sender));
+        return Ok(ShowsManager {
+            shows,
+            channels: patch.channel_names(),
+            mix_modes: patch.channel_mix_modes(),
+            wipe_positions,
+            home: patch.home_levels(),
+            shows_json_path: shows_json_path.to_string(),
+            reloads,
+            errors,
+            idle,
+            idle_state: IdleState {
+                beats: 0.,
+                level: 0.,
+                last_update: Instant::now(),
+                deck_offsets: Vec::new(),
+            },
+        });
+    }
+
+    // Swaps in the shows the watcher reloaded. A show that failed to load keeps playing
+    // its previous version and its error is kept for errors() until it loads again.
+    pub fn reload_changed(&mut self) {
+        while let Ok(reload) = self.reloads.try_recv() {
+            let (idle, titles, loaded) = match reload {
+                Reload::ManifestError(error) => {
+                    self.errors.insert(self.shows_json_path.clone(), error);
+                    continue;
+                }
+                Reload::Shows { idle, titles, loaded } => (idle, titles, loaded),
+            };
+            self.errors.remove(&self.shows_json_path);
+            self.idle = idle;
+            if let Some(titles) = titles {
+                self.shows.retain(|title, _| titles.contains(title));
+                let shows_json_path = &self.shows_json_path;
+                self.errors.retain(|title, _| titles.contains(title) || title == shows_json_path);
+            }
+            for (title, show) in loaded {
+                match show {
+                    Ok(show) => {
+                        self.errors.remove(&title);
+                        self.shows.insert(title, show);
+                    }
+                    Err(error) => {
+                        self.errors.insert(title, error);
+                    }
+                }
+            }
+        }
+    }
+
+    // Shows (or the manifest) that failed to load, as "title: error".
+    pub fn errors(&self) -> Vec<String> {
+        return self
+            .errors
+            .iter()
+            .map(|(title, error)| format!("{}: {}", title, error))
+            .sorted()
+            .collect();
+    }
+
+    // Where in the show to be at `beats` past its cue, or None when it should be dark.
+    fn playback_position(show: &LoadedShow, beats: f64) -> Option<f64> {
+        if beats < 0. {
+            return None;
+        }
+        let length = match show.length {
+            Some(length) => length,
+            None => return Some(beats),
+        };
+        return match show.playback {
+            Playback::Loop => Some(beats.rem_euclid(length)),
+            Playback::Hold => Some(beats.min(length)),
+            Playback::Blackout if beats >= length => None,
+            Playback::Blackout => Some(beats),
+            Playback::PingPong => {
+                let position = beats.rem_euclid(2. * length);
+                Some(if position > length {
+                    2. * length - position
+                } else {
+                    position
+                })
+            }
+        };
+    }
+
+    fn get_image_frame(show: &ImageShow, position: f64) -> &Vec<u8> {
+        let frame_index = (position * show.frames_per_beat).floor() as usize;
+        return &show.frames[frame_index.min(show.frames.len() - 1)];
+    }
+
+    // offset is how far the deck has played past the show's cue, in beats.
+    // deck is 0 and cue None for the idle show.
+    fn get_show_levels(
+        show: &LoadedShow,
+        title: &str,
+        offset: f64,
+        weight: f64,
+        mixer: &MixerState,
+        deck: usize,
+        cue: Option<&XmlCueInfo>,
+    ) -> Vec<(String, f64)> {
+        let position = match ShowsManager::playback_position(show, offset) {
+            Some(position) => position,
+            None => return Vec::new(),
+        };
+        return match &show.show {
+            Show::Image(show) => show
+                .channels
+                .iter()
+                .zip(ShowsManager::get_image_frame(show, position))
+                .map(|(channel, level)| (channel.clone(), *level as f64 / 255.))
+                .collect(),
+            Show::Procedural(show) => show.get_levels(position, mixer).into_iter().collect(),
+            Show::Scripted(show) => show
+                .get_levels(title, position, weight, deck, cue)
+                .into_iter()
+                .collect(),
+        };
+    }
+
+    fn get_cue_levels(
+        &self,
+        title: &str,
+        beats: f64,
+        cue_weight: f64,
+        gain: f64,
+        mixer: &MixerState,
+        deck: usize,
+        cue: Option<&XmlCueInfo>,
+    ) -> HashMap<String, f64> {
+        return match self.shows.get(title) {
+            Some(show) => ShowsManager::get_show_levels(show, title, beats, gain, mixer, deck, cue)
+                .into_iter()
+                .map(|(channel, level)| {
+                    // positions aren't dimmed
+                    let weight = if self.home.contains_key(&channel) {
+                        1.
+                    } else {
+                        cue_weight
+                    };
+                    (channel, level * weight)
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+    }
+
+    // A deck's levels before mixing, blending from the previous cue's show while the
+    // current cue's transition runs.
+    fn get_deck_levels(&self, deck: &DeckShow, mixer: &MixerState) -> HashMap<String, f64> {
+        let levels = self.get_cue_levels(
+            &deck.title,
+            deck.beats,
+            deck.cue_weight,
+            deck.gain(),
+            mixer,
+            deck.deck,
+            Some(&deck.cue),
+        );
+        let (length, wipe) = match deck.transition {
+            Transition::Cut => return levels,
+            Transition::Crossfade(length) => (length, false),
+            Transition::Wipe(length) => (length, true),
+        };
+        let progress = (deck.beats / length).clamp(0., 1.);
+        let previous = match &deck.previous {
+            Some(previous) if progress < 1. => previous,
+            _ => return levels,
+        };
+        let previous_levels = self.get_cue_levels(
+            &previous.title,
+            previous.beats,
+            previous.cue_weight,
+            deck.gain(),
+            mixer,
+            deck.deck,
+            Some(&previous.cue),
+        );
+        let channels: HashSet<&String> = levels.keys().chain(previous_levels.keys()).collect();
+        return channels
+            .into_iter()
+            .map(|channel| {
+                // a head only one of the shows moves stays where that show puts it
+                let missing = if self.home.contains_key(channel) {
+                    None
+                } else {
+                    Some(0.)
+                };
+                let current = levels.get(channel).copied().or(missing);
+                let before = previous_levels.get(channel).copied().or(missing);
+                let (current, before) = (
+                    current.or(before).unwrap_or(0.),
+                    before.or(current).unwrap_or(0.),
+                );
+                let level = if !wipe {
+                    current * progress + before * (1. - progress)
+                } else if self.wipe_positions.get(channel).copied().unwrap_or(0.) < progress {
+                    current
+                } else {
+                    before
+                };
+                (channel.clone(), level)
+            })
+            .collect();
+    }
+
+    // Moves the idle show on and fades it towards full while no deck show is heard, and
+    // towards dark while one is. It follows the beats of the loudest playing deck, and
+    // runs at its bpm while no deck is playing.
+    pub fn advance_idle(&mut self, mixer: &MixerState, decks: &Vec<DeckShow>) {
+        let idle = match &self.idle {
+            Some(idle) => idle,
+            None => return,
+        };
+        let state = &mut self.idle_state;
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_update).as_secs_f64();
+        state.last_update = now;
+        state.deck_offsets.resize(mixer.decks.len(), (0., now));
+        let mut followed: Option<(f64, f64)> = None;
+        for (deck, (last_offset, last_moved)) in
+            mixer.decks.iter().zip(state.deck_offsets.iter_mut())
+        {
+            let step = deck.beat_offset - *last_offset;
+            if step != 0. {
+                *last_moved = now;
+            }
+            // jumps (seeking, loading a track) and stopped decks don't count
+            let playing = (0. ..IDLE_MAX_STEP).contains(&step)
+                && now.duration_since(*last_moved) < IDLE_STOPPED_AFTER;
+            if playing && deck.gain > followed.map_or(0., |(_, gain)| gain) {
+                followed = Some((step, deck.gain));
+            }
+            *last_offset = deck.beat_offset;
+        }
+        let advance = followed.map_or(elapsed * idle.bpm / 60., |(step, _)| step);
+        state.beats += advance;
+
+        let cue_heard = decks
+            .iter()
+            .any(|deck| deck.gain() > 0. && self.shows.contains_key(&deck.title));
+        let fade_step = if idle.fade > 0. {
+            advance / idle.fade
+        } else {
+            1.
+        };
+        state.level = if cue_heard {
+            (state.level - fade_step).max(0.)
+        } else {
+            (state.level + fade_step).min(1.)
+        };
+    }
+
+    // Mixes the shows of every deck with an active show, and the idle show as far as it
+    // has faded in, into one frame, per channel according to its group's mix mode,
+    // clamped to 0..1.
+    pub fn get_combined_frame(
+        &self,
+        decks: Vec<DeckShow>,
+        mixer: &MixerState,
+    ) -> HashMap<String, f64> {
+        let mut frame: HashMap<String, f64> = self
+            .channels
+            .iter()
+            .map(|channel| (channel.clone(), self.home.get(channel).copied().unwrap_or(0.)))
+            .collect();
+        // the weight of the layer that positioned each head, as the heaviest one does
+        let mut positioned: HashMap<String, f64> = HashMap::new();
+        let mut layers: Vec<(HashMap<String, f64>, Option<&DeckShow>)> = decks
+            .iter()
+            .map(|deck| (self.get_deck_levels(deck, mixer), Some(deck)))
+            .collect();
+        if let Some(idle) = self.idle.as_ref().filter(|_| self.idle_state.level > 0.) {
+            let level = self.idle_state.level;
+            let levels = self.get_cue_levels(
+                &idle.show,
+                self.idle_state.beats,
+                level,
+                level,
+                mixer,
+                0,
+                None,
+            );
+            layers.push((levels, None));
+        }
+        for (levels, deck) in layers {
+            for (channel, level) in levels {
+                let mode = self.mix_modes.get(&channel).copied().unwrap_or(MixMode::Sum);
+                // the idle show isn't on a deck, so only its fade weights it
+                let weight = deck.map_or(1., |deck| deck.mix_weight(mode));
+                if self.home.contains_key(&channel) {
+                    let weight =
+                        deck.map_or(self.idle_state.level, |deck| deck.cue_weight * weight);
+                    if weight > positioned.get(&channel).copied().unwrap_or(0.) {
+                        positioned.insert(channel.clone(), weight);
+                        frame.insert(channel, level);
+                    }
+                    continue;
+                }
+                let weighted = level * weight;
+                let mixed = frame.entry(channel).or_insert(0.);
+                *mixed = match mode {
+                    MixMode::Htp => mixed.max(weighted),
+                    MixMode::Sum | MixMode::Crossfade => *mixed + weighted,
+                };
+            }
+        }
+        for level in frame.values_mut() {
+            *level = level.clamp(0., 1.);
+        }
+        return frame;
+    }
+}
+
+impl ShowsWatcher {
+    // Modification times of the manifest and of the shows it lists.
+    fn scan_modified(&self) -> HashMap<PathBuf, SystemTime> {
+        let show_paths: Vec<PathBuf> = read_manifest(&self.shows_json_path)
+            .map_or(Vec::new(), |json| json.shows.iter().map(|s| PathBuf::from(&s.path)).collect());
+        return show_paths
+            .into_iter()
+            .chain([PathBuf::from(&self.shows_json_path)])
+            .filter_map(|path| {
+                let time = metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((normalize_path(&path), time))
+            })
+            .collect();
+    }
+
+    // Loads the shows whose files changed since the last check, or every show when the
+    // manifest changed; None when nothing changed.
+    fn check(&mut self) -> Option<Reload> {
+        let modified = self.scan_modified();
+        let changed: HashSet<PathBuf> = modified
+            .iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(*time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.modified = modified;
+        if changed.is_empty() {
+            return None;
+        }
+        let manifest_changed = changed.contains(&normalize_path(Path::new(&self.shows_json_path)));
+        let json = match read_manifest(&self.shows_json_path) {
+            Ok(json) => json,
+            Err(error) => return Some(Reload::ManifestError(error)),
+        };
+        let wanted: Vec<ShowJson> = json
+            .shows
+            .into_iter()
+            .filter(|s| is_wanted(self.wanted_titles.as_ref(), json.idle.as_ref(), &s.title))
+            .collect();
+        let titles = manifest_changed.then(|| wanted.iter().map(|s| s.title.clone()).collect());
+        let loaded = wanted
+            .into_iter()
+            .filter(|s| manifest_changed || changed.contains(&normalize_path(Path::new(&s.path))))
+            .map(|s| {
+                let show = ShowsManager::load_show(&s, &self.patch, &self.image_channels)
+                    .map_err(|error| format!("{}: {}", s.path, error));
+                (s.title, show)
+            })
+            .collect();
+        return Some(Reload::Shows { idle: json.idle, titles, loaded });
+    }
+
+    fn run(mut self, reloads: Sender<Reload>) {
+        loop {
+            thread::sleep(RELOAD_INTERVAL);
+            if let Some(reload) = self.check() {
+                // the manager is gone once the application quits
+                if reloads.send(reload).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::rekordbox::{TrackState, XmlTrackInfo};
+
+// Only the deck's track ID, title and artist are read from rekordbox's memory, so
+// there is no matching by file location or duration yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchConfidence {
+    TrackId,
+    TitleArtist,
+    Unmatched,
+}
+
+impl fmt::Display for MatchConfidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            MatchConfidence::TrackId => "track ID",
+            MatchConfidence::TitleArtist => "title and artist",
+            MatchConfidence::Unmatched => "unmatched",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// Lowercase alphanumeric words, so "Track (Original Mix)" and "track  original mix" agree.
+pub fn normalize_text(text: &str) -> String {
+    return text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+pub struct TrackIndex {
+    // ID -> the track and its normalized title, to check the ID against the deck's title
+    by_id: HashMap<u32, (usize, String)>,
+    by_title_artist: HashMap<(String, String), Vec<usize>>,
+}
+
+impl TrackIndex {
+    pub fn make(xml_tracks: &Vec<XmlTrackInfo>) -> TrackIndex {
+        let mut index = TrackIndex {
+            by_id: HashMap::new(),
+            by_title_artist: HashMap::new(),
+        };
+        for (i, track) in xml_tracks.iter().enumerate() {
+            // tracks loaded from analysis files alone have no ID
+            if track.id != 0 {
+                index
+                    .by_id
+                    .entry(track.id)
+                    .or_insert((i, normalize_text(&track.title)));
+            }
+            index
+                .by_title_artist
+                .entry((normalize_text(&track.title), normalize_text(&track.artist)))
+                .or_insert(Vec::new())
+                .push(i);
+        }
+        return index;
+    }
+
+    // Tries the track ID, then title and artist for decks whose ID isn't in the collection.
+    // IDs drift between the live library and older exports, so an ID whose track has
+    // another title than the deck's is taken for a stale one and skipped.
+    pub fn find(&self, track: &TrackState) -> Option<(usize, MatchConfidence)> {
+        let title = normalize_text(&track.title);
+        if let Some((i, id_title)) = self.by_id.get(&track.id) {
+            if title.is_empty() || title == *id_title {
+                return Some((*i, MatchConfidence::TrackId));
+            }
+        }
+        if title.is_empty() {
+            return None;
+        }
+        // the first of several tracks with the same title and artist, e.g. different edits
+        let candidates = self
+            .by_title_artist
+            .get(&(title, normalize_text(&track.artist)))?;
+        return Some((candidates[0], MatchConfidence::TitleArtist));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xml_track(id: u32, title: &str, artist: &str) -> XmlTrackInfo {
+        return XmlTrackInfo {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            id,
+            location: String::new(),
+            duration: None,
+            cues: Vec::new(),
+        };
+    }
+
+    fn deck(id: u32, title: &str, artist: &str) -> TrackState {
+        return TrackState {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            id,
+            beat_offset: 0.,
+            last_cue: None,
+            previous_cue: None,
+            match_confidence: MatchConfidence::Unmatched,
+            matched_id: None,
+        };
+    }
+
+    fn collection() -> TrackIndex {
+        return TrackIndex::make(&vec![
+            xml_track(1, "Atlas (Original Mix)", "Someone"),
+            xml_track(2, "Sparkle", "Someone Else"),
+        ]);
+    }
+
+    #[test]
+    fn matching_id() {
+        let index = collection();
+        assert_eq!(
+            index.find(&deck(1, "atlas  original mix", "someone")),
+            Some((0, MatchConfidence::TrackId))
+        );
+        // no title read from the deck: the ID is all there is to go on
+        assert_eq!(
+            index.find(&deck(2, "", "")),
+            Some((1, MatchConfidence::TrackId))
+        );
+    }
+
+    #[test]
+    fn colliding_id() {
+        let index = collection();
+        // ID 1 now belongs to another track, found by its title and artist instead
+        assert_eq!(
+            index.find(&deck(1, "Sparkle", "Someone Else")),
+            Some((1, MatchConfidence::TitleArtist))
+        );
+        assert_eq!(index.find(&deck(2, "Unknown", "Nobody")), None);
+    }
+
+    #[test]
+    fn unknown_id() {
+        let index = collection();
+        assert_eq!(
+            index.find(&deck(7, "Atlas (Original Mix)", "Someone")),
+            Some((0, MatchConfidence::TitleArtist))
+        );
+        assert_eq!(index.find(&deck(7, "", "")), None);
+    }
+}
@@ -0,0 +1,286 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Instant;
+
+use crate::rekordbox::{
+    normalize_location, parse_rekordbox_xml, tempo_point_from_attributes, xml_cue_from_attributes,
+    xml_track_from_attributes, XmlPlaylist, XmlTrackInfo,
+};
+
+// Loads the same tracks as parse_rekordbox_xml in a single pass over the XML file,
+// reading it in buffered chunks without building a document tree, and stops at the
+// end of COLLECTION. Only one track's attributes are held at a time.
+
+type Attributes = Vec<(String, String)>;
+
+fn read_attributes(element: &BytesStart) -> Result<Attributes, Box<dyn Error>> {
+    let mut attributes: Attributes = Vec::new();
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        attributes.push((
+            String::from_utf8_lossy(attribute.key.local_name().into_inner()).to_string(),
+            attribute.unescape_value()?.to_string(),
+        ));
+    }
+    return Ok(attributes);
+}
+
+fn lookup<'a>(attributes: &'a Attributes, name: &str) -> Option<&'a str> {
+    return attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str());
+}
+
+fn finish_track(
+    track_attributes: &Attributes,
+    tempo_points: &Vec<(f64, f64)>,
+    marks: &Vec<Attributes>,
+) -> Result<XmlTrackInfo, String> {
+    let cues = if tempo_points.is_empty() {
+        Vec::new()
+    } else {
+        marks
+            .iter()
+            .map(|mark| xml_cue_from_attributes(|name| lookup(mark, name), tempo_points))
+            .collect::<Result<Vec<_>, String>>()?
+    };
+    return xml_track_from_attributes(|name| lookup(track_attributes, name), cues);
+}
+
+pub fn parse_rekordbox_xml_streaming(path: &String) -> Result<Vec<XmlTrackInfo>, Box<dyn Error>> {
+    println!("loading rekordbox xml");
+    let file = File::open(path).map_err(|error| format!("could not open {}: {}", path, error))?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let mut xml_tracks: Vec<XmlTrackInfo> = Vec::new();
+    let mut in_collection = false;
+    let mut track_attributes: Option<Attributes> = None;
+    let mut tempo_points: Vec<(f64, f64)> = Vec::new();
+    let mut marks: Vec<Attributes> = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|error| format!("could not parse {}: {}", path, error))?;
+        // "<TRACK/>" both opens and closes its element
+        let (opened, closed) = match &event {
+            Event::Start(element) => (Some(element), None),
+            Event::Empty(element) => (Some(element), Some(element.local_name().into_inner())),
+            Event::End(element) => (None, Some(element.local_name().into_inner())),
+            Event::Eof => break,
+            _ => (None, None),
+        };
+        if let Some(element) = opened {
+            match element.local_name().into_inner() {
+                b"COLLECTION" => in_collection = true,
+                b"TRACK" if in_collection && track_attributes.is_none() => {
+                    track_attributes = Some(read_attributes(element)?);
+                }
+                b"TEMPO" if track_attributes.is_some() => {
+                    let attributes = read_attributes(element)?;
+                    if let Some(tempo_point) =
+                        tempo_point_from_attributes(|name| lookup(&attributes, name))
+                    {
+                        tempo_points.push(tempo_point);
+                    }
+                }
+                b"POSITION_MARK" if track_attributes.is_some() => {
+                    marks.push(read_attributes(element)?);
+                }
+                _ => {}
+            }
+        }
+        match closed {
+            Some(b"TRACK") if in_collection => {
+                if let Some(track) = track_attributes.take() {
+                    xml_tracks.push(
+                        finish_track(&track, &tempo_points, &marks)
+                            .map_err(|error| format!("could not parse {}: {}", path, error))?,
+                    );
+                }
+                tempo_points.clear();
+                marks.clear();
+            }
+            Some(b"COLLECTION") => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    println!(
+        "Finished loading XML {}, found {} tracks with {} cue points",
+        path,
+        xml_tracks.len(),
+        xml_tracks.iter().map(|track| track.cues.len()).sum::<usize>()
+    );
+    return Ok(xml_tracks);
+}
+
+// The playlists of the XML's PLAYLISTS tree, read in a second streaming pass that skips
+// over COLLECTION. Playlist entries are keyed by TrackID (KeyType 0) or by Location
+// (KeyType 1); locations are looked up in the tracks already loaded from the same XML.
+pub fn parse_rekordbox_playlists(
+    path: &String,
+    xml_tracks: &Vec<XmlTrackInfo>,
+) -> Result<Vec<XmlPlaylist>, Box<dyn Error>> {
+    let ids_by_location: HashMap<&str, u32> = xml_tracks
+        .iter()
+        .filter(|track| track.id != 0)
+        .map(|track| (track.location.as_str(), track.id))
+        .collect();
+    let file = File::open(path).map_err(|error| format!("could not open {}: {}", path, error))?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut skipped: Vec<u8> = Vec::new();
+
+    let mut playlists: Vec<XmlPlaylist> = Vec::new();
+    let mut in_playlists = false;
+    // names of the open NODEs; the first is the unnamed root folder
+    let mut folders: Vec<String> = Vec::new();
+    // the open playlist, and whether its entries are keyed by location
+    let mut playlist: Option<(XmlPlaylist, bool)> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|error| format!("could not parse {}: {}", path, error))?;
+        let (opened, closed) = match &event {
+            Event::Start(element) if element.local_name().into_inner() == b"COLLECTION" => {
+                reader
+                    .read_to_end_into(element.name(), &mut skipped)
+                    .map_err(|error| format!("could not parse {}: {}", path, error))?;
+                skipped.clear();
+                (None, None)
+            }
+            Event::Start(element) => (Some(element), None),
+            Event::Empty(element) => (Some(element), Some(element.local_name().into_inner())),
+            Event::End(element) => (None, Some(element.local_name().into_inner())),
+            Event::Eof => break,
+            _ => (None, None),
+        };
+        if let Some(element) = opened {
+            match element.local_name().into_inner() {
+                b"PLAYLISTS" => in_playlists = true,
+                b"NODE" if in_playlists => {
+                    let attributes = read_attributes(element)?;
+                    folders.push(lookup(&attributes, "Name").unwrap_or("").to_string());
+                    if lookup(&attributes, "Type") != Some("0") {
+                        let keyed_by_location = lookup(&attributes, "KeyType") == Some("1");
+                        let path = folders[1..].join("/");
+                        let track_ids = Vec::new();
+                        playlist = Some((XmlPlaylist { path, track_ids }, keyed_by_location));
+                    }
+                }
+                b"TRACK" if in_playlists => {
+                    let attributes = read_attributes(element)?;
+                    if let (Some((playlist, keyed_by_location)), Some(key)) =
+                        (playlist.as_mut(), lookup(&attributes, "Key"))
+                    {
+                        let id = if *keyed_by_location {
+                            ids_by_location.get(normalize_location(key).as_str()).copied()
+                        } else {
+                            key.parse::<u32>().ok()
+                        };
+                        playlist.track_ids.extend(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match closed {
+            Some(b"NODE") if in_playlists => {
+                folders.pop();
+                if let Some((playlist, _)) = playlist.take() {
+                    playlists.push(playlist);
+                }
+            }
+            Some(b"PLAYLISTS") => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    return Ok(playlists);
+}
+
+fn write_synthetic_collection(path: &std::path::Path, track_count: usize) -> std::io::Result<()> {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\n<DJ_PLAYLISTS Version=\"1.0.0\">\n",
+    );
+    xml.push_str("  <PRODUCT Name=\"rekordbox\" Version=\"6.7.4\" Company=\"AlphaTheta\"/>\n");
+    xml.push_str(&format!("  <COLLECTION Entries=\"{}\">\n", track_count));
+    for i in 0..track_count {
+        let bpm = 100. + (i % 60) as f64;
+        let first_beat = (i % 500) as f64 / 1000.;
+        xml.push_str(&format!(
+            "    <TRACK TrackID=\"{id}\" Name=\"Track {id} (Original Mix) &amp; Friends\" Artist=\"Artist {artist}\" \
+Composer=\"\" Album=\"Album {album}\" Grouping=\"\" Genre=\"House\" Kind=\"MP3 File\" Size=\"{size}\" \
+TotalTime=\"{duration}\" DiscNumber=\"0\" TrackNumber=\"{number}\" Year=\"2023\" AverageBpm=\"{bpm:.2}\" \
+DateAdded=\"2023-01-01\" BitRate=\"320\" SampleRate=\"44100\" Comments=\"\" PlayCount=\"0\" Rating=\"0\" \
+Location=\"file://localhost/C:/Music/Artist%20{artist}/Track%20{id}.mp3\" Remixer=\"\" Tonality=\"8A\" Label=\"\" Mix=\"\">\n",
+            id = i + 1,
+            artist = i % 997,
+            album = i % 3001,
+            size = 8_000_000 + i,
+            duration = 180 + i % 240,
+            number = i % 12,
+            bpm = bpm,
+        ));
+        xml.push_str(&format!(
+            "      <TEMPO Inizio=\"{:.3}\" Bpm=\"{:.2}\" Metro=\"4/4\" Battito=\"1\"/>\n",
+            first_beat, bpm
+        ));
+        for cue in 0..8 {
+            let start = first_beat + (cue * 32) as f64 * 60. / bpm;
+            let name = match cue % 4 {
+                0 => format!("EW:show=show{} fade=1b", cue),
+                1 => "Drop".to_string(),
+                _ => String::new(),
+            };
+            let num = if cue < 3 { cue as i32 } else { -1 };
+            xml.push_str(&format!(
+                "      <POSITION_MARK Name=\"{}\" Type=\"0\" Start=\"{:.3}\" Num=\"{}\"/>\n",
+                name, start, num
+            ));
+        }
+        xml.push_str("    </TRACK>\n");
+    }
+    xml.push_str("  </COLLECTION>\n  <PLAYLISTS>\n    <NODE Type=\"0\" Name=\"ROOT\" Count=\"0\"/>\n  </PLAYLISTS>\n</DJ_PLAYLISTS>\n");
+    return std::fs::write(path, xml);
+}
+
+// Times both XML loaders on a generated collection of the given size.
+pub fn benchmark_xml_loaders(track_count: usize) -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join("rust_tuber_synthetic_collection.xml");
+    write_synthetic_collection(&path, track_count)?;
+    let path_string = path.to_string_lossy().to_string();
+    println!(
+        "wrote synthetic collection with {} tracks ({} MB) to {}",
+        track_count,
+        std::fs::metadata(&path)?.len() / 1_000_000,
+        path_string
+    );
+
+    let start = Instant::now();
+    let document_tracks = parse_rekordbox_xml(&path_string)?;
+    let document_time = start.elapsed();
+
+    let start = Instant::now();
+    let streaming_tracks = parse_rekordbox_xml_streaming(&path_string)?;
+    let streaming_time = start.elapsed();
+
+    let cue_count = |tracks: &Vec<XmlTrackInfo>| tracks.iter().map(|track| track.cues.len()).sum::<usize>();
+    if document_tracks.len() != streaming_tracks.len()
+        || cue_count(&document_tracks) != cue_count(&streaming_tracks)
+    {
+        return Err("loaders disagree on the synthetic collection".into());
+    }
+    println!("document loader:  {:?}", document_time);
+    println!("streaming loader: {:?}", streaming_time);
+    std::fs::remove_file(&path)?;
+    return Ok(());
+}